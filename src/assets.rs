@@ -2,12 +2,74 @@ use std::rc::Rc;
 
 use wasm_bindgen::prelude::*;
 
-use crate::{rates::Rate, montecarlo::{Period, Lifespan}};
+use crate::{rates::{Rate, cumulative_inflation}, montecarlo::{Period, Lifespan}};
+use crate::simplifying_assumption;
 
 #[derive(Debug)]
 #[wasm_bindgen]
 pub struct AssetAllocation {
     stocks_glide: Vec<f64>,
+    funded_ratio: Option<FundedRatioRule>,
+}
+
+// What a run knows about itself that a dynamic allocation might want to react to
+#[derive(Copy,Clone,Debug)]
+pub struct AllocationContext {
+    pub portfolio: f64,
+    pub monthly_spending: f64,
+    pub life_expectancy_years: f64,
+}
+
+// Maps the ratio of the portfolio to the present value of the spending it still has to fund onto
+// a stock percentage.  Below floor_ratio stocks are held at stocks_at_floor, above target_ratio
+// they're held at stocks_at_target, and in between they're interpolated linearly.
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct FundedRatioRule {
+    floor_ratio: f64,
+    stocks_at_floor: f64,
+    target_ratio: f64,
+    stocks_at_target: f64,
+    real_discount_rate: f64,
+}
+
+#[wasm_bindgen]
+impl FundedRatioRule {
+    #[wasm_bindgen(constructor)]
+    pub fn new(floor_ratio: f64, stocks_at_floor: f64, target_ratio: f64, stocks_at_target: f64, real_discount_rate: f64) -> FundedRatioRule {
+        assert!(floor_ratio < target_ratio);
+        assert!((0.0..=1.0).contains(&stocks_at_floor));
+        assert!((0.0..=1.0).contains(&stocks_at_target));
+        assert!(real_discount_rate > -1.0);
+
+        FundedRatioRule { floor_ratio, stocks_at_floor, target_ratio, stocks_at_target, real_discount_rate }
+    }
+}
+
+impl FundedRatioRule {
+    fn funded_ratio(&self, context: &AllocationContext) -> f64 {
+        simplifying_assumption!("Spending is assumed to continue at today's level in real terms for \
+            exactly the remaining life expectancy when valuing the liability.");
+
+        let annual_spending = context.monthly_spending * 12.0;
+        let years = context.life_expectancy_years;
+        let r = self.real_discount_rate;
+
+        let annuity_factor = if r == 0.0 { years } else { (1.0 - (1.0 + r).powf(-years)) / r };
+        let liability = annual_spending * annuity_factor;
+
+        if liability <= 0.0 {
+            f64::INFINITY
+        } else {
+            context.portfolio / liability
+        }
+    }
+
+    fn stocks(&self, context: &AllocationContext) -> f64 {
+        let frac = ((self.funded_ratio(context) - self.floor_ratio) / (self.target_ratio - self.floor_ratio)).clamp(0.0, 1.0);
+
+        frac * (self.stocks_at_target - self.stocks_at_floor) + self.stocks_at_floor
+    }
 }
 
 #[wasm_bindgen]
@@ -18,7 +80,7 @@ impl AssetAllocation {
         assert!(stocks_glide.iter().min_by(|x,y| x.partial_cmp(y).unwrap()).unwrap() >= &0.0);
         assert!(stocks_glide.iter().max_by(|x,y| x.partial_cmp(y).unwrap()).unwrap() <= &1.0);
 
-        AssetAllocation{ stocks_glide }
+        AssetAllocation{ stocks_glide, funded_ratio: None }
     }
 
     #[wasm_bindgen]
@@ -35,7 +97,89 @@ impl AssetAllocation {
             stocks_glide[i] = frac * (end_stocks - start_stocks) + start_stocks;
         }
 
-        AssetAllocation { stocks_glide }
+        AssetAllocation { stocks_glide, funded_ratio: None }
+    }
+
+    #[wasm_bindgen]
+    pub fn new_step_glide(timeline: &GlideTimeline, anchor: GlideAnchor, initial_stocks: f64, step_years: Vec<f64>, step_stocks: Vec<f64>) -> AssetAllocation {
+        assert_eq!(step_years.len(), step_stocks.len());
+        assert!(step_years.windows(2).all(|w| w[0] < w[1]));
+        assert!(std::iter::once(&initial_stocks).chain(step_stocks.iter()).all(|s| (0.0..=1.0).contains(s)));
+
+        let steps: Vec<(usize, f64)> = step_years.iter().zip(step_stocks).map(|(years, stocks)| (timeline.period(anchor, *years), stocks)).collect();
+        let len = steps.last().map_or(1, |(period, _)| period + 1);
+
+        let stocks_glide = (0..len).map(|i| {
+            steps.iter().rev().find(|(period, _)| *period <= i).map_or(initial_stocks, |(_, stocks)| *stocks)
+        }).collect();
+
+        AssetAllocation { stocks_glide, funded_ratio: None }
+    }
+
+    // curvature > 0 holds stocks high and drops them late, curvature < 0 drops them early
+    #[wasm_bindgen]
+    pub fn new_exponential_glide(timeline: &GlideTimeline, anchor: GlideAnchor, start: GlidePoint, end: GlidePoint, curvature: f64) -> AssetAllocation {
+        let stocks_glide = glide_segment(timeline.signed_period(anchor, start.years), start.stocks, timeline.signed_period(anchor, end.years), end.stocks, |t| {
+            if curvature == 0.0 {
+                t
+            } else {
+                ((curvature * t).exp() - 1.0) / (curvature.exp() - 1.0)
+            }
+        });
+
+        AssetAllocation { stocks_glide, funded_ratio: None }
+    }
+
+    // An S-curve centered halfway between start and end.  Higher steepness concentrates more of
+    // the move around the midpoint.
+    #[wasm_bindgen]
+    pub fn new_logistic_glide(timeline: &GlideTimeline, anchor: GlideAnchor, start: GlidePoint, end: GlidePoint, steepness: f64) -> AssetAllocation {
+        assert!(steepness > 0.0);
+
+        let logistic = |t: f64| 1.0 / (1.0 + (-steepness * (t - 0.5)).exp());
+        let stocks_glide = glide_segment(timeline.signed_period(anchor, start.years), start.stocks, timeline.signed_period(anchor, end.years), end.stocks, |t| {
+            (logistic(t) - logistic(0.0)) / (logistic(1.0) - logistic(0.0))
+        });
+
+        AssetAllocation { stocks_glide, funded_ratio: None }
+    }
+
+    // The "N minus age" family of rules, e.g. base_age = 110 gives 110 minus age in stocks
+    #[wasm_bindgen]
+    pub fn new_age_based(timeline: &GlideTimeline, base_age: f64, min_stocks: f64, max_stocks: f64) -> AssetAllocation {
+        assert!((0.0..=1.0).contains(&min_stocks));
+        assert!((0.0..=1.0).contains(&max_stocks));
+        assert!(min_stocks <= max_stocks);
+
+        // Past this age the rule is pinned at min_stocks, so there's no need to go any further
+        let len = timeline.period(GlideAnchor::Age, base_age - min_stocks * 100.0) + 1;
+
+        let stocks_glide = (0..len).map(|i| {
+            let age = (timeline.age_months(i) / 12) as f64;
+            ((base_age - age) / 100.0).clamp(min_stocks, max_stocks)
+        }).collect();
+
+        AssetAllocation { stocks_glide, funded_ratio: None }
+    }
+
+    // Stocks glide down to their lowest point around retirement and then back up afterwards
+    #[wasm_bindgen]
+    pub fn new_bond_tent(timeline: &GlideTimeline, anchor: GlideAnchor, start: GlidePoint, bottom: GlidePoint, end: GlidePoint) -> AssetAllocation {
+        assert!(bottom.stocks <= start.stocks);
+        assert!(bottom.stocks <= end.stocks);
+
+        let bottom_period = timeline.signed_period(anchor, bottom.years);
+        let mut stocks_glide = glide_segment(timeline.signed_period(anchor, start.years), start.stocks, bottom_period, bottom.stocks, |t| t);
+        let rise = glide_segment(bottom_period, bottom.stocks, timeline.signed_period(anchor, end.years), end.stocks, |t| t);
+        stocks_glide.extend_from_slice(&rise[stocks_glide.len()..]);
+
+        AssetAllocation { stocks_glide, funded_ratio: None }
+    }
+
+    // The glide is followed whenever no context is available, e.g. while still working
+    #[wasm_bindgen]
+    pub fn new_funded_ratio(glide: &AssetAllocation, rule: FundedRatioRule) -> AssetAllocation {
+        AssetAllocation { stocks_glide: glide.stocks_glide.clone(), funded_ratio: Some(rule) }
     }
 
     #[wasm_bindgen]
@@ -53,11 +197,219 @@ impl AssetAllocation {
     }
 }
 
-#[derive(Debug)]
+impl AssetAllocation {
+    pub fn stocks_with_context(&self, period: Period, context: Option<&AllocationContext>) -> f64 {
+        match (&self.funded_ratio, context) {
+            (Some(rule), Some(context)) => rule.stocks(context),
+            _ => self.stocks(period)
+        }
+    }
+
+    pub fn bonds_with_context(&self, period: Period, context: Option<&AllocationContext>) -> f64 {
+        1.0 - self.stocks_with_context(period, context)
+    }
+}
+
+// Holds start_stocks through start_period and end_stocks from end_period on, moving between them
+// along shape, which maps the fraction of the glide completed onto [0, 1].  Either period can be
+// before the simulation starts, in which case the glide picks up wherever it would be by then.
+fn glide_segment(start_period: i64, start_stocks: f64, end_period: i64, end_stocks: f64, shape: impl Fn(f64) -> f64) -> Vec<f64> {
+    assert!((0.0..=1.0).contains(&start_stocks));
+    assert!((0.0..=1.0).contains(&end_stocks));
+
+    (0..=i64::max(end_period, 0)).map(|i| {
+        if i >= end_period {
+            end_stocks
+        } else if i <= start_period {
+            start_stocks
+        } else {
+            let frac = (i - start_period) as f64 / (end_period - start_period) as f64;
+            shape(frac) * (end_stocks - start_stocks) + start_stocks
+        }
+    }).collect()
+}
+
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+#[wasm_bindgen]
+pub enum GlideAnchor {
+    Age,
+    Retirement
+}
+
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct GlidePoint {
+    pub years: f64,
+    pub stocks: f64
+}
+
+#[wasm_bindgen]
+impl GlidePoint {
+    #[wasm_bindgen(constructor)]
+    pub fn new(years: f64, stocks: f64) -> GlidePoint {
+        GlidePoint { years, stocks }
+    }
+}
+
+// Converts ages and offsets from retirement (in years) into the periods used by AssetAllocation
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct GlideTimeline {
+    starting_age_months: usize,
+    retirement_period: usize
+}
+
+#[wasm_bindgen]
+impl GlideTimeline {
+    #[wasm_bindgen(constructor)]
+    pub fn new(age_years: usize, age_months: usize, career_periods: usize) -> GlideTimeline {
+        GlideTimeline { starting_age_months: age_years * 12 + age_months, retirement_period: career_periods }
+    }
+
+    // Periods that would be before the start of the simulation are clamped to period 0
+    #[wasm_bindgen]
+    pub fn period(&self, anchor: GlideAnchor, years: f64) -> usize {
+        std::cmp::max(self.signed_period(anchor, years), 0) as usize
+    }
+}
+
+impl GlideTimeline {
+    // Negative before the start of the simulation
+    fn signed_period(&self, anchor: GlideAnchor, years: f64) -> i64 {
+        let months = (years * 12.0).round() as i64;
+        match anchor {
+            GlideAnchor::Age => months - self.starting_age_months as i64,
+            GlideAnchor::Retirement => months + self.retirement_period as i64
+        }
+    }
+
+    fn age_months(&self, period: usize) -> usize {
+        self.starting_age_months + period
+    }
+}
+
+#[derive(Clone,Copy,Debug)]
+#[wasm_bindgen]
+pub struct AdvisoryFeeTier {
+    pub floor: f64,
+    pub rate: f64,
+}
+
+simplifying_assumption!("Advisory fee tier floors are not adjusted for inflation.  Over a long \
+    simulation this will push balances into the cheaper upper tiers sooner than a real advisor \
+    would, slightly understating advisory fees.");
+#[derive(Clone,Debug,Default)]
+#[wasm_bindgen]
+pub struct FeeSettings {
+    account_expense_ratio: f64,
+    stocks_expense_ratio: f64,
+    bonds_expense_ratio: f64,
+    advisory_tiers: Vec<AdvisoryFeeTier>,
+}
+
+impl FeeSettings {
+    pub fn new(account_expense_ratio: f64, stocks_expense_ratio: f64, bonds_expense_ratio: f64, advisory_tiers: Vec<AdvisoryFeeTier>) -> FeeSettings {
+        assert!(advisory_tiers.windows(2).all(|w| w[0].floor < w[1].floor));
+
+        FeeSettings { account_expense_ratio, stocks_expense_ratio, bonds_expense_ratio, advisory_tiers }
+    }
+
+    pub fn none() -> FeeSettings {
+        Self::new(0.0, 0.0, 0.0, vec![])
+    }
+
+    // Annual advisory fee on the given assets under management.  Tiers are marginal, so each
+    // tier's rate only applies to the part of the balance that falls inside that tier.
+    fn advisory_fee(&self, balance: f64) -> f64 {
+        let mut fee = 0.0;
+
+        for (i, tier) in self.advisory_tiers.iter().enumerate() {
+            if balance <= tier.floor {
+                break;
+            }
+
+            let ceil = match self.advisory_tiers.get(i + 1) {
+                Some(next) => f64::min(balance, next.floor),
+                None => balance
+            };
+            fee += (ceil - tier.floor) * tier.rate;
+        }
+
+        fee
+    }
+
+    fn monthly_fee(&self, stocks: f64, bonds: f64) -> f64 {
+        let total = stocks + bonds;
+        let annual = stocks * self.stocks_expense_ratio
+            + bonds * self.bonds_expense_ratio
+            + total * self.account_expense_ratio
+            + self.advisory_fee(total);
+
+        annual / 12.0
+    }
+}
+
+#[wasm_bindgen]
+impl FeeSettings {
+    #[wasm_bindgen(constructor)]
+    pub fn new_from_js(account_expense_ratio: f64, stocks_expense_ratio: f64, bonds_expense_ratio: f64, advisory_tier_floors: Vec<f64>, advisory_tier_rates: Vec<f64>) -> FeeSettings {
+        assert_eq!(advisory_tier_floors.len(), advisory_tier_rates.len());
+
+        let advisory_tiers = advisory_tier_floors.into_iter().zip(advisory_tier_rates)
+            .map(|(floor, rate)| { AdvisoryFeeTier{floor, rate} })
+            .collect();
+
+        Self::new(account_expense_ratio, stocks_expense_ratio, bonds_expense_ratio, advisory_tiers)
+    }
+}
+
+// How withdrawals from an account are taxed
+simplifying_assumption!("Withdrawals from taxable accounts are all treated as basis, so they aren't \
+    taxed.  This understates taxes for accounts with large capital gains.");
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+#[wasm_bindgen]
+pub enum AccountTaxTreatment {
+    Taxable,
+    // Traditional 401k/IRA, withdrawals are ordinary income
+    TaxDeferred,
+    // Roth, withdrawals aren't taxed
+    TaxFree
+}
+
+#[derive(Clone,Debug)]
 #[wasm_bindgen]
 pub struct AccountSettings {
     starting_balance: f64,
-    allocation: Rc<AssetAllocation>
+    allocation: Rc<AssetAllocation>,
+    fees: Rc<FeeSettings>,
+    // None if it should follow how the account is contributed to, e.g. tax-deferred for pre-tax
+    // contributions, or taxable otherwise
+    tax_treatment: Option<AccountTaxTreatment>
+}
+
+simplifying_assumption!("Cash buckets don't earn any interest.");
+// Separate cash, bond and stock balances that are each invested on their own instead of being
+// rebalanced to the account's allocation every period, for bucket strategies.  Withdrawals come
+// out of cash first, then bonds, then stocks.
+#[derive(Copy,Clone,Debug,Default,PartialEq)]
+pub struct Buckets {
+    pub cash: f64,
+    pub bonds: f64,
+    pub stocks: f64
+}
+
+impl Buckets {
+    pub fn total(&self) -> f64 {
+        self.cash + self.bonds + self.stocks
+    }
+
+    fn withdraw(&mut self, mut amount: f64) {
+        for bucket in [&mut self.cash, &mut self.bonds, &mut self.stocks] {
+            let from_bucket = f64::min(amount, *bucket);
+            *bucket -= from_bucket;
+            amount -= from_bucket;
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -65,7 +417,17 @@ pub struct Account {
     starting_balance: f64,
     balance: Vec<f64>,
     allocation: Rc<AssetAllocation>,
-    rates: Rc<Vec<Rate>>
+    rates: Rc<Vec<Rate>>,
+    fees: Rc<FeeSettings>,
+    fees_paid: Vec<f64>,
+    // Fraction of each period's employer money that's vested
+    vested_pct: Vec<f64>,
+    // Part of each period's balance that came from employer contributions during the simulation,
+    // which is all that vesting applies to
+    employer_balance: Vec<f64>,
+    tax_treatment: AccountTaxTreatment,
+    // Current bucket balances, once split into buckets
+    buckets: Option<Buckets>
 }
 
 #[wasm_bindgen]
@@ -74,42 +436,128 @@ impl AccountSettings {
     pub fn new_from_js(starting_balance: f64, allocation: AssetAllocation) -> AccountSettings {
         Self::new(starting_balance, Rc::new(allocation))
     }
+
+    #[wasm_bindgen]
+    pub fn new_with_fees_from_js(starting_balance: f64, allocation: AssetAllocation, fees: FeeSettings) -> AccountSettings {
+        Self::new_with_fees(starting_balance, Rc::new(allocation), Rc::new(fees))
+    }
+
+    #[wasm_bindgen]
+    pub fn new_with_tax_treatment_from_js(starting_balance: f64, allocation: AssetAllocation, fees: FeeSettings, tax_treatment: AccountTaxTreatment) -> AccountSettings {
+        Self::new_with_tax_treatment(starting_balance, Rc::new(allocation), Rc::new(fees), tax_treatment)
+    }
 }
 
 impl AccountSettings {
     pub fn new(starting_balance: f64, allocation: Rc<AssetAllocation>) -> AccountSettings {
-        AccountSettings { starting_balance, allocation }
+        Self::new_with_fees(starting_balance, allocation, Rc::new(FeeSettings::none()))
+    }
+
+    pub fn new_with_fees(starting_balance: f64, allocation: Rc<AssetAllocation>, fees: Rc<FeeSettings>) -> AccountSettings {
+        AccountSettings { starting_balance, allocation, fees, tax_treatment: None }
+    }
+
+    pub fn new_with_tax_treatment(starting_balance: f64, allocation: Rc<AssetAllocation>, fees: Rc<FeeSettings>, tax_treatment: AccountTaxTreatment) -> AccountSettings {
+        AccountSettings { starting_balance, allocation, fees, tax_treatment: Some(tax_treatment) }
+    }
+
+    // Uses the given tax treatment unless one was set explicitly
+    pub fn with_default_tax_treatment(&self, tax_treatment: AccountTaxTreatment) -> AccountSettings {
+        AccountSettings { tax_treatment: Some(self.tax_treatment.unwrap_or(tax_treatment)), ..self.clone() }
     }
 
     pub fn create_account(&self, lifespan: Lifespan, rates: Rc<Vec<Rate>>) -> Account {
         assert_eq!(rates.len(), lifespan.periods());
         let balance = vec![0.0; lifespan.periods()];
+        let fees_paid = vec![0.0; lifespan.periods()];
+        let vested_pct = vec![1.0; lifespan.periods()];
+        let employer_balance = vec![0.0; lifespan.periods()];
 
         Account {
             starting_balance: self.starting_balance,
             balance,
             allocation: Rc::clone(&self.allocation),
-            rates: rates
+            rates,
+            fees: Rc::clone(&self.fees),
+            fees_paid,
+            vested_pct,
+            employer_balance,
+            tax_treatment: self.tax_treatment.unwrap_or(AccountTaxTreatment::Taxable),
+            buckets: None
         }
     }
 }
 
 impl Account {
     pub fn rebalance_and_invest_next_period(&mut self, period: Period) {
+        self.rebalance_and_invest_next_period_with_context(period, None);
+    }
+
+    pub fn rebalance_and_invest_next_period_with_context(&mut self, period: Period, context: Option<&AllocationContext>) {
         assert!(period.get() < self.balance.len());
         assert_eq!(self.balance[period.get()], 0.0);
 
-        let balance = if period.get() > 0 { self.balance[(period-1).get()] } else { self.starting_balance };
-        let stocks_new = balance * self.allocation.stocks(period) * self.rates[(period).get()].stocks();
-        let bonds_new = balance * self.allocation.bonds(period) * self.rates[(period).get()].bonds();
-        self.balance[period.get()] = stocks_new + bonds_new;
+        let rate = &self.rates[period.get()];
+        if let Some(buckets) = &mut self.buckets {
+            buckets.stocks *= rate.stocks();
+            buckets.bonds *= rate.bonds();
+
+            let total = buckets.total();
+            let fee = self.fees.monthly_fee(buckets.stocks, buckets.bonds + buckets.cash);
+            if total > 0.0 {
+                let after_fee = (total - fee) / total;
+                buckets.cash *= after_fee;
+                buckets.bonds *= after_fee;
+                buckets.stocks *= after_fee;
+            }
+
+            self.fees_paid[period.get()] = fee;
+            self.balance[period.get()] = buckets.total();
+            self.invest_employer_balance(period);
+            return;
+        }
+
+        let balance = self.balance_before(period);
+        let stocks_new = balance * self.allocation.stocks_with_context(period, context) * self.rates[(period).get()].stocks();
+        let bonds_new = balance * self.allocation.bonds_with_context(period, context) * self.rates[(period).get()].bonds();
+        let fee = self.fees.monthly_fee(stocks_new, bonds_new);
+
+        self.fees_paid[period.get()] = fee;
+        self.balance[period.get()] = stocks_new + bonds_new - fee;
+        self.invest_employer_balance(period);
+    }
+
+    // Carries the balance into the period without investing it, e.g. before the job the account
+    // belongs to has started
+    pub fn carry_over_next_period(&mut self, period: Period) {
+        assert_eq!(self.balance[period.get()], 0.0);
+
+        self.balance[period.get()] = self.balance_before(period);
+        if period.get() > 0 {
+            self.employer_balance[period.get()] = self.employer_balance[(period - 1).get()];
+        }
+    }
+
+    // Employer money grows at the same rate as the rest of the account
+    fn invest_employer_balance(&mut self, period: Period) {
+        let balance = self.balance_before(period);
+        if period.get() > 0 && balance > 0.0 {
+            self.employer_balance[period.get()] = self.employer_balance[(period - 1).get()] * self.balance[period.get()] / balance;
+        }
     }
     
     pub fn withdraw_from_period(&mut self, amount: f64, period: Period) {
         assert!(period.get() < self.balance.len());
         assert!(amount <= self.balance[period.get()]);
-    
+
+        // Withdrawals come out of employer money and everything else pro rata
+        if self.balance[period.get()] > 0.0 {
+            self.employer_balance[period.get()] *= 1.0 - amount / self.balance[period.get()];
+        }
         self.balance[period.get()] -= amount;
+        if let Some(buckets) = &mut self.buckets {
+            buckets.withdraw(amount);
+        }
     }
 
     pub fn attempt_withdrawal_with_shortfall(&mut self, amount: f64, period: Period) -> f64 {
@@ -122,19 +570,204 @@ impl Account {
 
     pub fn deposit(&mut self, amount: f64, period: Period) {
         self.balance[period.get()] += amount;
+        if let Some(buckets) = &mut self.buckets {
+            buckets.cash += amount;
+        }
+    }
+
+    // Deposits employer contributions, which are subject to vesting
+    pub fn deposit_employer(&mut self, amount: f64, period: Period) {
+        self.deposit(amount, period);
+        self.employer_balance[period.get()] += amount;
+    }
+
+    // The balance carried into the given period, before it's invested
+    pub fn balance_before(&self, period: Period) -> f64 {
+        if period.get() > 0 { self.balance[(period-1).get()] } else { self.starting_balance }
     }
 
     pub fn balance(&self) -> &Vec<f64> {
         &self.balance
     }
+
+    pub fn tax_treatment(&self) -> AccountTaxTreatment {
+        self.tax_treatment
+    }
+
+    // An empty account with the same allocation and fees, e.g. for reinvesting required minimum
+    // distributions or receiving Roth conversions
+    pub fn create_empty_account(&self, tax_treatment: AccountTaxTreatment) -> Account {
+        Account {
+            starting_balance: 0.0,
+            balance: vec![0.0; self.balance.len()],
+            allocation: Rc::clone(&self.allocation),
+            rates: Rc::clone(&self.rates),
+            fees: Rc::clone(&self.fees),
+            fees_paid: vec![0.0; self.balance.len()],
+            vested_pct: vec![1.0; self.balance.len()],
+            employer_balance: vec![0.0; self.balance.len()],
+            tax_treatment,
+            buckets: None
+        }
+    }
+
+    // Stops rebalancing the account and splits this period's balance into buckets, with whatever
+    // isn't in cash or bonds in stocks
+    pub fn split_into_buckets(&mut self, cash: f64, bonds: f64, period: Period) {
+        let balance = self.balance[period.get()];
+        let cash = f64::min(cash, balance);
+        let bonds = f64::min(bonds, balance - cash);
+
+        self.buckets = Some(Buckets { cash, bonds, stocks: balance - cash - bonds });
+    }
+
+    pub fn buckets(&self) -> Option<Buckets> {
+        self.buckets
+    }
+
+    // Moving money between buckets doesn't change the balance
+    pub fn buckets_mut(&mut self) -> Option<&mut Buckets> {
+        self.buckets.as_mut()
+    }
+
+    // Target stock allocation for the given period
+    pub fn stocks(&self, period: Period) -> f64 {
+        self.allocation.stocks(period)
+    }
+
+    pub fn fees_paid(&self) -> &Vec<f64> {
+        &self.fees_paid
+    }
+
+    pub fn total_fees_paid(&self) -> f64 {
+        self.fees_paid.iter().sum()
+    }
+
+    // Rolls the account into one with the given allocation and fees from the next period on, e.g.
+    // an IRA that an old employer plan is rolled into.  The balance and fee history stay with the
+    // account, and it keeps the tax treatment of the plan.
+    pub fn roll_over(&mut self, rollover: &AccountSettings) {
+        self.allocation = Rc::clone(&rollover.allocation);
+        self.fees = Rc::clone(&rollover.fees);
+    }
+
+    pub fn set_vested_pct(&mut self, vested_pct: f64, period: Period) {
+        self.vested_pct[period.get()] = vested_pct;
+    }
+
+    // Removes the unvested employer money, returning how much was forfeited
+    pub fn forfeit_unvested(&mut self, period: Period) -> f64 {
+        let employer_balance = self.employer_balance[period.get()];
+        let forfeited = employer_balance * (1.0 - self.vested_pct[period.get()]);
+
+        self.withdraw_from_period(forfeited, period);
+        self.employer_balance[period.get()] = employer_balance - forfeited;
+        self.vested_pct[period.get()] = 1.0;
+
+        forfeited
+    }
+
+    pub fn vested_balance(&self) -> Vec<f64> {
+        self.balance.iter().zip(self.unvested_balance()).map(|(balance, unvested)| balance - unvested).collect()
+    }
+
+    pub fn unvested_balance(&self) -> Vec<f64> {
+        self.employer_balance.iter().zip(self.vested_pct.iter()).map(|(employer, pct)| employer * (1.0 - pct)).collect()
+    }
 }
 
+// A ladder of bonds bought all at once, with one rung maturing at the start of each year to pay
+// for that year's spending.  TIPS rungs are indexed to inflation from the purchase date, nominal
+// rungs pay a fixed amount.
+simplifying_assumption!("Bond ladders are held to maturity and never default, and each year's \
+    rung is paid out in equal monthly amounts.");
+#[derive(Clone,Debug)]
+pub struct BondLadder {
+    purchased: Period,
+    // In dollars as of the purchase date
+    annual_payouts: Vec<f64>,
+    inflation_indexed: bool
+}
+
+impl BondLadder {
+    pub fn new(purchased: Period, annual_payouts: Vec<f64>, inflation_indexed: bool) -> BondLadder {
+        BondLadder { purchased, annual_payouts, inflation_indexed }
+    }
+
+    // Cost of the rungs given annual yields by years to maturity, real for TIPS.  Maturities past
+    // the end of the curve use its last yield.
+    pub fn price(annual_payouts: &[f64], yield_curve: &[f64]) -> f64 {
+        assert!(!yield_curve.is_empty());
+
+        annual_payouts.iter().enumerate().map(|(years, payout)| {
+            let rate = yield_curve[usize::min(years, yield_curve.len() - 1)];
+            payout / (1.0 + rate).powi(years as i32)
+        }).sum()
+    }
+
+    pub fn monthly_payout(&self, period: Period, rates: &[Rate]) -> f64 {
+        let year = (period.get() - self.purchased.get()) / 12;
+        if year >= self.annual_payouts.len() {
+            return 0.0;
+        }
+
+        let indexation = if self.inflation_indexed {
+            cumulative_inflation(rates, period) / cumulative_inflation(rates, self.purchased)
+        } else {
+            1.0
+        };
 
+        self.annual_payouts[year] * indexation / 12.0
+    }
+}
 
 #[cfg(test)]
 mod tests {
+    use assert_float_eq::*;
+
     use super::*;
 
+    #[test]
+    fn account_buckets() {
+        let rates = Rc::new(vec![Rate::new(2.0, 1.5, 1.0); 2]);
+        let mut account = AccountSettings::new(1000.0, Rc::new(AssetAllocation::new(vec![0.5; 2]))).create_account(Lifespan::new(2), rates);
+
+        account.rebalance_and_invest_next_period(Period::new(0));
+        account.split_into_buckets(250.0, 500.0, Period::new(0));
+        assert_eq!(account.buckets(), Some(Buckets { cash: 250.0, bonds: 500.0, stocks: 1000.0 }));
+
+        // Each bucket grows on its own instead of being rebalanced
+        account.rebalance_and_invest_next_period(Period::new(1));
+        assert_eq!(account.balance()[1], 3000.0);
+
+        // Cash goes first
+        account.withdraw_from_period(500.0, Period::new(1));
+        assert_eq!(account.buckets(), Some(Buckets { cash: 0.0, bonds: 500.0, stocks: 2000.0 }));
+        account.deposit(100.0, Period::new(1));
+        assert_eq!(account.buckets().unwrap().cash, 100.0);
+    }
+
+    #[test]
+    fn bondladder_price() {
+        assert_eq!(BondLadder::price(&[1000.0, 1000.0], &[0.0]), 2000.0);
+        assert_float_absolute_eq!(BondLadder::price(&[1000.0, 1100.0, 1210.0], &[0.05, 0.1]), 3000.0);
+    }
+
+    #[test]
+    fn bondladder_monthlypayout() {
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 30];
+        rates[13] = Rate::new(1.0, 1.0, 1.5);
+
+        let tips = BondLadder::new(Period::new(1), vec![1200.0, 2400.0], true);
+        assert_eq!(tips.monthly_payout(Period::new(12), &rates), 100.0);
+        assert_eq!(tips.monthly_payout(Period::new(13), &rates), 200.0);
+        assert_eq!(tips.monthly_payout(Period::new(14), &rates), 300.0);
+        assert_eq!(tips.monthly_payout(Period::new(25), &rates), 0.0);
+
+        let nominal = BondLadder::new(Period::new(1), vec![1200.0, 2400.0], false);
+        assert_eq!(nominal.monthly_payout(Period::new(14), &rates), 200.0);
+    }
+
     #[test]
     fn assetallocation_vec() {
         let assets = AssetAllocation::new(vec![1.0, 1.0, 1.0, 1.0, 0.5, 0.75]);
@@ -189,7 +822,7 @@ mod tests {
     fn account_rebalanceandinvest_period0() {
         // Use powers of two to make the floating point math work out roundly
         let allocation = Rc::new(AssetAllocation::new_linear_glide(4, 0.75, 2, 0.25));
-        let mut account = Account{ starting_balance: 1024.0, balance: vec![0.0], allocation: allocation, rates: Rc::new(vec![Rate::new(2.0, 0.5, 1.0)]) };
+        let mut account = Account{ starting_balance: 1024.0, balance: vec![0.0], allocation: allocation, rates: Rc::new(vec![Rate::new(2.0, 0.5, 1.0)]), fees: Default::default(), fees_paid: vec![0.0], vested_pct: vec![1.0], employer_balance: vec![0.0], tax_treatment: AccountTaxTreatment::Taxable, buckets: None };
         
         account.rebalance_and_invest_next_period(Period::new(0));
         assert_eq!(account.balance, vec![1664.0]);
@@ -199,16 +832,218 @@ mod tests {
     fn account_rebalanceandinvest_period1() {
         // Use powers of two to make the floating point math work out roundly
         let allocation = Rc::new(AssetAllocation::new_linear_glide(4, 0.75, 2, 0.25));
-        let mut account = Account{ starting_balance: 1024.0, balance: vec![1664.0, 0.0], allocation: allocation, rates: Rc::new(vec![Rate::new(2.0, 0.5, 1.0), Rate::new(2.0, 0.5, 1.0)]) };
+        let mut account = Account{ starting_balance: 1024.0, balance: vec![1664.0, 0.0], allocation: allocation, rates: Rc::new(vec![Rate::new(2.0, 0.5, 1.0), Rate::new(2.0, 0.5, 1.0)]), fees: Default::default(), fees_paid: vec![0.0; 2], vested_pct: vec![1.0; 2], employer_balance: vec![0.0; 2], tax_treatment: AccountTaxTreatment::Taxable, buckets: None };
         
         account.rebalance_and_invest_next_period(Period::new(1));
         assert_eq!(account.balance, vec![1664.0, 2704.0]);
     }
 
+    #[test]
+    fn assetallocation_fundedratio() {
+        let glide = AssetAllocation::new(vec![0.75]);
+        let rule = FundedRatioRule::new(1.0, 0.25, 2.0, 0.5, 0.0);
+        let assets = AssetAllocation::new_funded_ratio(&glide, rule);
+
+        // Without a context we fall back to the glide
+        assert_eq!(assets.stocks(Period::new(0)), 0.75);
+        assert_eq!(assets.stocks_with_context(Period::new(0), None), 0.75);
+
+        // Liability is 12 * 100 * 10 = 12000
+        let context = |portfolio| AllocationContext { portfolio, monthly_spending: 100.0, life_expectancy_years: 10.0 };
+        assert_eq!(assets.stocks_with_context(Period::new(0), Some(&context(6000.0))), 0.25);
+        assert_eq!(assets.stocks_with_context(Period::new(0), Some(&context(12000.0))), 0.25);
+        assert_eq!(assets.stocks_with_context(Period::new(0), Some(&context(18000.0))), 0.375);
+        assert_eq!(assets.stocks_with_context(Period::new(0), Some(&context(24000.0))), 0.5);
+        assert_eq!(assets.stocks_with_context(Period::new(0), Some(&context(48000.0))), 0.5);
+        assert_eq!(assets.bonds_with_context(Period::new(0), Some(&context(18000.0))), 0.625);
+
+        // A glide-only allocation ignores the context
+        assert_eq!(glide.stocks_with_context(Period::new(0), Some(&context(6000.0))), 0.75);
+    }
+
+    #[test]
+    fn fundedratiorule_discounting() {
+        let rule = FundedRatioRule::new(1.0, 0.25, 2.0, 0.5, 0.03);
+        let context = AllocationContext { portfolio: 100000.0, monthly_spending: 500.0, life_expectancy_years: 20.0 };
+
+        assert_float_absolute_eq!(rule.funded_ratio(&context), 100000.0 / (6000.0 * 14.877474860), 0.000001);
+
+        let context = AllocationContext { portfolio: 100000.0, monthly_spending: 0.0, life_expectancy_years: 20.0 };
+        assert_eq!(rule.stocks(&context), 0.5);
+    }
+
+    #[test]
+    fn account_rebalanceandinvest_withcontext() {
+        let glide = AssetAllocation::new(vec![0.75]);
+        let allocation = Rc::new(AssetAllocation::new_funded_ratio(&glide, FundedRatioRule::new(1.0, 0.25, 2.0, 0.5, 0.0)));
+        let mut account = AccountSettings::new(1024.0, allocation).create_account(Lifespan::new(2), Rc::new(vec![Rate::new(2.0, 0.5, 1.0); 2]));
+
+        account.rebalance_and_invest_next_period(Period::new(0));
+        assert_eq!(account.balance()[0], 1664.0);
+
+        // Fully funded at the floor, so only 25% in stocks
+        let context = AllocationContext { portfolio: 1664.0, monthly_spending: 1664.0 / 12.0, life_expectancy_years: 1.0 };
+        account.rebalance_and_invest_next_period_with_context(Period::new(1), Some(&context));
+        assert_eq!(account.balance()[1], 1664.0 * 0.25 * 2.0 + 1664.0 * 0.75 * 0.5);
+    }
+
+    #[test]
+    fn glidetimeline_period() {
+        let timeline = GlideTimeline::new(40, 6, 300);
+
+        assert_eq!(timeline.period(GlideAnchor::Age, 40.5), 0);
+        assert_eq!(timeline.period(GlideAnchor::Age, 30.0), 0);
+        assert_eq!(timeline.period(GlideAnchor::Age, 45.0), 54);
+        assert_eq!(timeline.period(GlideAnchor::Retirement, 0.0), 300);
+        assert_eq!(timeline.period(GlideAnchor::Retirement, -5.0), 240);
+        assert_eq!(timeline.period(GlideAnchor::Retirement, 2.5), 330);
+        assert_eq!(timeline.period(GlideAnchor::Retirement, -30.0), 0);
+    }
+
+    #[test]
+    fn assetallocation_stepglide() {
+        let timeline = GlideTimeline::new(60, 0, 60);
+        let assets = AssetAllocation::new_step_glide(&timeline, GlideAnchor::Retirement, 0.75, vec![-2.0, 0.0, 5.0], vec![0.5, 0.25, 0.375]);
+
+        assert_eq!(assets.stocks(Period::new(0)), 0.75);
+        assert_eq!(assets.stocks(Period::new(35)), 0.75);
+        assert_eq!(assets.stocks(Period::new(36)), 0.5);
+        assert_eq!(assets.stocks(Period::new(59)), 0.5);
+        assert_eq!(assets.stocks(Period::new(60)), 0.25);
+        assert_eq!(assets.stocks(Period::new(119)), 0.25);
+        assert_eq!(assets.stocks(Period::new(120)), 0.375);
+        assert_eq!(assets.stocks(Period::new(1000)), 0.375);
+    }
+
+    #[test]
+    fn assetallocation_exponentialglide() {
+        let timeline = GlideTimeline::new(50, 0, 180);
+        let assets = AssetAllocation::new_exponential_glide(&timeline, GlideAnchor::Age, GlidePoint::new(55.0, 1.0), GlidePoint::new(65.0, 0.5), 2.0);
+
+        assert_eq!(assets.stocks(Period::new(0)), 1.0);
+        assert_eq!(assets.stocks(Period::new(60)), 1.0);
+        assert_float_absolute_eq!(assets.stocks(Period::new(120)), 1.0 - 0.5 * (1.0f64.exp() - 1.0) / (2.0f64.exp() - 1.0));
+        assert_eq!(assets.stocks(Period::new(180)), 0.5);
+        assert_eq!(assets.stocks(Period::new(500)), 0.5);
+
+        // Positive curvature holds stocks higher than a straight line for longer
+        assert!(assets.stocks(Period::new(120)) > 0.75);
+
+        let linear = AssetAllocation::new_exponential_glide(&timeline, GlideAnchor::Age, GlidePoint::new(55.0, 1.0), GlidePoint::new(65.0, 0.5), 0.0);
+        assert_eq!(linear.stocks(Period::new(120)), 0.75);
+    }
+
+    #[test]
+    fn assetallocation_glidebeforesimulation() {
+        // Starting at 70, after the whole glide is over
+        let timeline = GlideTimeline::new(70, 0, 0);
+        let assets = AssetAllocation::new_exponential_glide(&timeline, GlideAnchor::Age, GlidePoint::new(55.0, 1.0), GlidePoint::new(65.0, 0.5), 2.0);
+        assert_eq!(assets.stocks(Period::new(0)), 0.5);
+        assert_eq!(assets.stocks(Period::new(100)), 0.5);
+
+        let tent = AssetAllocation::new_bond_tent(&timeline, GlideAnchor::Age, GlidePoint::new(55.0, 0.8), GlidePoint::new(65.0, 0.3), GlidePoint::new(75.0, 0.6));
+        assert_eq!(tent.stocks(Period::new(0)), 0.3);
+        assert_eq!(tent.stocks(Period::new(60)), 0.6);
+
+        // Starting at 60, halfway through the glide, picks up from where it would be by then
+        let timeline = GlideTimeline::new(60, 0, 0);
+        let assets = AssetAllocation::new_exponential_glide(&timeline, GlideAnchor::Age, GlidePoint::new(55.0, 1.0), GlidePoint::new(65.0, 0.5), 0.0);
+        assert_float_absolute_eq!(assets.stocks(Period::new(0)), 0.75);
+        assert_float_absolute_eq!(assets.stocks(Period::new(30)), 0.625);
+        assert_eq!(assets.stocks(Period::new(60)), 0.5);
+
+        let tent = AssetAllocation::new_bond_tent(&timeline, GlideAnchor::Age, GlidePoint::new(55.0, 0.8), GlidePoint::new(65.0, 0.3), GlidePoint::new(75.0, 0.6));
+        assert_float_absolute_eq!(tent.stocks(Period::new(0)), 0.55);
+        assert_eq!(tent.stocks(Period::new(60)), 0.3);
+        assert_float_absolute_eq!(tent.stocks(Period::new(120)), 0.45);
+    }
+
+    #[test]
+    fn assetallocation_logisticglide() {
+        let timeline = GlideTimeline::new(50, 0, 180);
+        let assets = AssetAllocation::new_logistic_glide(&timeline, GlideAnchor::Retirement, GlidePoint::new(-10.0, 0.9), GlidePoint::new(0.0, 0.3), 10.0);
+
+        assert_eq!(assets.stocks(Period::new(60)), 0.9);
+        assert_float_absolute_eq!(assets.stocks(Period::new(120)), 0.6);
+        assert_eq!(assets.stocks(Period::new(180)), 0.3);
+        assert_eq!(assets.stocks(Period::new(181)), 0.3);
+
+        // Most of the move happens in the middle of the glide
+        assert!(assets.stocks(Period::new(72)) > 0.85);
+        assert!(assets.stocks(Period::new(168)) < 0.35);
+    }
+
+    #[test]
+    fn assetallocation_agebased() {
+        let timeline = GlideTimeline::new(25, 6, 480);
+        let assets = AssetAllocation::new_age_based(&timeline, 110.0, 0.2, 0.8);
+
+        assert_eq!(assets.stocks(Period::new(0)), 0.8);
+        assert_eq!(assets.stocks(Period::new(4 * 12 + 6)), 0.8);
+        assert_float_absolute_eq!(assets.stocks(Period::new(5 * 12 + 6)), 0.79);
+        assert_float_absolute_eq!(assets.stocks(Period::new(24 * 12 + 6)), 0.6);
+        assert_float_absolute_eq!(assets.stocks(Period::new(24 * 12 + 17)), 0.6);
+        assert_float_absolute_eq!(assets.stocks(Period::new(44 * 12 + 6)), 0.4);
+        assert_float_absolute_eq!(assets.stocks(Period::new(64 * 12 + 6)), 0.2);
+        assert_eq!(assets.stocks(Period::new(2000)), 0.2);
+    }
+
+    #[test]
+    fn assetallocation_bondtent() {
+        let timeline = GlideTimeline::new(55, 0, 120);
+        let assets = AssetAllocation::new_bond_tent(&timeline, GlideAnchor::Retirement, GlidePoint::new(-5.0, 0.75), GlidePoint::new(0.0, 0.25), GlidePoint::new(5.0, 0.5));
+
+        assert_eq!(assets.stocks(Period::new(0)), 0.75);
+        assert_eq!(assets.stocks(Period::new(60)), 0.75);
+        assert_eq!(assets.stocks(Period::new(90)), 0.5);
+        assert_eq!(assets.stocks(Period::new(120)), 0.25);
+        assert_eq!(assets.stocks(Period::new(150)), 0.375);
+        assert_eq!(assets.stocks(Period::new(180)), 0.5);
+        assert_eq!(assets.stocks(Period::new(1000)), 0.5);
+    }
+
+    #[test]
+    fn feesettings_advisorytiers() {
+        let tiers = vec![AdvisoryFeeTier { floor: 0.0, rate: 0.01 }, AdvisoryFeeTier { floor: 1000.0, rate: 0.005 }, AdvisoryFeeTier { floor: 3000.0, rate: 0.0025 }];
+        let fees = FeeSettings::new(0.0, 0.0, 0.0, tiers);
+
+        assert_eq!(fees.advisory_fee(0.0), 0.0);
+        assert_eq!(fees.advisory_fee(512.0), 5.12);
+        assert_eq!(fees.advisory_fee(2000.0), 15.0);
+        assert_eq!(fees.advisory_fee(5000.0), 25.0);
+    }
+
+    #[test]
+    fn feesettings_monthlyfee() {
+        let tiers = vec![AdvisoryFeeTier { floor: 0.0, rate: 0.0625 }];
+        let fees = FeeSettings::new(0.125, 0.25, 0.5, tiers);
+
+        // stocks: 768 * 0.25, bonds: 256 * 0.5, account: 1024 * 0.125, advisory: 1024 * 0.0625
+        assert_eq!(fees.monthly_fee(768.0, 256.0), (192.0 + 128.0 + 128.0 + 64.0) / 12.0);
+        assert_eq!(FeeSettings::none().monthly_fee(768.0, 256.0), 0.0);
+    }
+
+    #[test]
+    fn account_rebalanceandinvest_withfees() {
+        // Use powers of two to make the floating point math work out roundly
+        let allocation = Rc::new(AssetAllocation::new_linear_glide(4, 0.75, 2, 0.25));
+        let fees = Rc::new(FeeSettings::new(0.0, 0.1875, 0.375, vec![]));
+        let mut account = Account{ starting_balance: 1024.0, balance: vec![0.0, 0.0], allocation, rates: Rc::new(vec![Rate::new(2.0, 0.5, 1.0), Rate::new(2.0, 0.5, 1.0)]), fees, fees_paid: vec![0.0; 2], vested_pct: vec![1.0; 2], employer_balance: vec![0.0; 2], tax_treatment: AccountTaxTreatment::Taxable, buckets: None };
+
+        // stocks: 1536 * 0.1875 / 12 = 24, bonds: 128 * 0.375 / 12 = 4
+        account.rebalance_and_invest_next_period(Period::new(0));
+        assert_eq!(account.balance, vec![1636.0, 0.0]);
+        assert_eq!(account.fees_paid(), &vec![28.0, 0.0]);
+
+        account.rebalance_and_invest_next_period(Period::new(1));
+        assert_eq!(account.total_fees_paid(), 28.0 + account.fees_paid()[1]);
+        assert!(account.fees_paid()[1] > 0.0);
+    }
+
     #[test]
     fn account_withdrawall() {
         let allocation = Rc::new(AssetAllocation::new_linear_glide(4, 0.75, 2, 0.25));
-        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default() };
+        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default(), fees: Default::default(), fees_paid: vec![0.0; 2], vested_pct: vec![1.0; 2], employer_balance: vec![0.0; 2], tax_treatment: AccountTaxTreatment::Taxable, buckets: None };
 
         account.withdraw_from_period(1024.0, Period::new(1));
         assert_eq!(account.balance, vec![1024.0, 0.0]);
@@ -218,7 +1053,7 @@ mod tests {
     #[test]
     fn account_withdrawsome() {
         let allocation = Rc::new(AssetAllocation::new_linear_glide(4, 0.75, 2, 0.25));
-        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default() };
+        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default(), fees: Default::default(), fees_paid: vec![0.0; 2], vested_pct: vec![1.0; 2], employer_balance: vec![0.0; 2], tax_treatment: AccountTaxTreatment::Taxable, buckets: None };
 
         account.withdraw_from_period(512.0, Period::new(1));
         assert_eq!(account.balance, vec![1024.0, 512.0]);
@@ -229,7 +1064,7 @@ mod tests {
     #[should_panic]
     fn account_withdrawmore() {
         let allocation = Rc::new(AssetAllocation::new_linear_glide(4, 0.75, 2, 0.25));
-        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default() };
+        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default(), fees: Default::default(), fees_paid: vec![0.0; 2], vested_pct: vec![1.0; 2], employer_balance: vec![0.0; 2], tax_treatment: AccountTaxTreatment::Taxable, buckets: None };
 
         account.withdraw_from_period(2048.0, Period::new(1));
     }
@@ -237,7 +1072,7 @@ mod tests {
     #[test]
     fn account_attemptwithdrawall() {
         let allocation = Rc::new(AssetAllocation::new_linear_glide(4, 0.75, 2, 0.25));
-        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default() };
+        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default(), fees: Default::default(), fees_paid: vec![0.0; 2], vested_pct: vec![1.0; 2], employer_balance: vec![0.0; 2], tax_treatment: AccountTaxTreatment::Taxable, buckets: None };
 
         let shortfall = account.attempt_withdrawal_with_shortfall(1024.0, Period::new(1));
         assert_eq!(account.balance, vec![1024.0, 0.0]);
@@ -248,7 +1083,7 @@ mod tests {
     #[test]
     fn account_attemptwithdrawsome() {
         let allocation = Rc::new(AssetAllocation::new_linear_glide(4, 0.75, 2, 0.25));
-        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default() };
+        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default(), fees: Default::default(), fees_paid: vec![0.0; 2], vested_pct: vec![1.0; 2], employer_balance: vec![0.0; 2], tax_treatment: AccountTaxTreatment::Taxable, buckets: None };
 
         let shortfall = account.attempt_withdrawal_with_shortfall(512.0, Period::new(1));
         assert_eq!(account.balance, vec![1024.0, 512.0]);
@@ -259,10 +1094,25 @@ mod tests {
     #[test]
     fn account_attemptwithdrawmore() {
         let allocation = Rc::new(AssetAllocation::new_linear_glide(4, 0.75, 2, 0.25));
-        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default() };
+        let mut account = Account{ starting_balance: 1024.0, balance: vec![1024.0; 2], allocation: allocation, rates: Default::default(), fees: Default::default(), fees_paid: vec![0.0; 2], vested_pct: vec![1.0; 2], employer_balance: vec![0.0; 2], tax_treatment: AccountTaxTreatment::Taxable, buckets: None };
 
         let shortfall = account.attempt_withdrawal_with_shortfall(2048.0, Period::new(1));
         assert_eq!(account.balance, vec![1024.0, 0.0]);
         assert_eq!(shortfall, 1024.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn account_vesting_forfeit() {
+        let allocation = Rc::new(AssetAllocation::new(vec![0.5]));
+        let mut account = Account{ starting_balance: 0.0, balance: vec![1000.0, 1000.0], allocation, rates: Default::default(), fees: Default::default(), fees_paid: vec![0.0; 2], vested_pct: vec![0.2, 0.6], employer_balance: vec![1000.0, 500.0], tax_treatment: AccountTaxTreatment::Taxable, buckets: None };
+
+        // Only employer money is subject to vesting
+        assert_eq!(account.vested_balance(), vec![200.0, 800.0]);
+        assert_eq!(account.unvested_balance(), vec![800.0, 200.0]);
+
+        assert_eq!(account.forfeit_unvested(Period::new(1)), 200.0);
+        assert_eq!(account.balance, vec![1000.0, 800.0]);
+        assert_eq!(account.employer_balance, vec![1000.0, 300.0]);
+        assert_eq!(account.unvested_balance(), vec![800.0, 0.0]);
+    }
+}
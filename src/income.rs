@@ -2,25 +2,77 @@ use std::rc::Rc;
 
 use wasm_bindgen::prelude::*;
 
-use crate::assets::{AccountSettings, Account};
+use crate::assets::{AccountSettings, Account, AccountTaxTreatment};
 use crate::montecarlo::{Period, Lifespan};
-use crate::rates::Rate;
+use crate::pension::{Pension, PensionSettings};
+use crate::rates::{Rate, cumulative_inflation};
 use crate::simplifying_assumption;
 use crate::taxes::{TaxCollector, Money};
 
 pub trait IncomeProvider {
     fn calculate_income_for_period(&mut self, period: Period, tax: &mut impl TaxCollector);
     fn get_net_income(&self) -> &Vec<f64>;
+    // Monthly earnings that count towards Social Security benefits
+    fn covered_earnings(&self) -> &[f64];
+    // Pensions earned by the end of the career
+    fn pensions(&self) -> Vec<Pension>;
     fn retire(self) -> (f64, Vec<Account>);
-    fn account_contributions(&self) -> &[AccountContribution];
+    // Pays a lump sum out of savings, returning how much couldn't be covered
+    fn pay_expense(&mut self, amount: f64, period: Period) -> f64;
+}
+
+// Employee payroll taxes.  The OASDI wage base is in today's dollars, while the Additional
+// Medicare Tax threshold is fixed in nominal dollars by law.
+simplifying_assumption!("The OASDI wage base is indexed to simulated inflation instead of the \
+    national average wage index.");
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct FicaSettings {
+    oasdi_rate: f64,
+    oasdi_wage_base: f64,
+    medicare_rate: f64,
+    additional_medicare_rate: f64,
+    additional_medicare_threshold: f64,
+    adjust_wage_base_for_inflation: bool
+}
+
+#[wasm_bindgen]
+impl FicaSettings {
+    #[wasm_bindgen(constructor)]
+    pub fn new(oasdi_rate: f64, oasdi_wage_base: f64, medicare_rate: f64, additional_medicare_rate: f64, additional_medicare_threshold: f64, adjust_wage_base_for_inflation: bool) -> FicaSettings {
+        FicaSettings { oasdi_rate, oasdi_wage_base, medicare_rate, additional_medicare_rate, additional_medicare_threshold, adjust_wage_base_for_inflation }
+    }
+
+    // Single filer
+    #[wasm_bindgen]
+    pub fn irs_2023() -> FicaSettings {
+        Self::new(0.062, 160200.0, 0.0145, 0.009, 200000.0, true)
+    }
+}
+
+impl FicaSettings {
+    fn wage_base(&self, inflation: f64) -> f64 {
+        if self.adjust_wage_base_for_inflation { self.oasdi_wage_base * inflation } else { self.oasdi_wage_base }
+    }
+
+    // Wages for the period that count towards Social Security, given this year's wages so far
+    fn covered_wages(&self, wages_this_year: f64, gross: f64, inflation: f64) -> f64 {
+        let wage_base = self.wage_base(inflation);
+        f64::min(wages_this_year + gross, wage_base) - f64::min(wages_this_year, wage_base)
+    }
+
+    fn withholding(&self, wages_this_year: f64, gross: f64, inflation: f64) -> f64 {
+        let over_threshold = |wages: f64| f64::max(wages - self.additional_medicare_threshold, 0.0);
+
+        self.oasdi_rate * self.covered_wages(wages_this_year, gross, inflation)
+            + self.medicare_rate * gross
+            + self.additional_medicare_rate * (over_threshold(wages_this_year + gross) - over_threshold(wages_this_year))
+    }
 }
 
-simplifying_assumption!("There is no cap on social security contributions/benefits. \
-    This will particularly impact high earners and will cause the social security \
-    deduction and benefit amount to be too high.");
 #[derive(Copy,Clone,Debug)]
 pub enum Fica {
-    Participant{ss_rate: f64},
+    Participant(FicaSettings),
     Exempt
 }
 
@@ -32,8 +84,8 @@ pub struct FicaJS {
 #[wasm_bindgen]
 impl FicaJS {
     #[wasm_bindgen]
-    pub fn participant(ss_rate: f64) -> FicaJS {
-        FicaJS{ fica: Fica::Participant { ss_rate }}
+    pub fn participant(settings: FicaSettings) -> FicaJS {
+        FicaJS{ fica: Fica::Participant(settings) }
     }
 
     #[wasm_bindgen]
@@ -71,13 +123,150 @@ pub enum AccountContributionTaxability {
     PostTax
 }
 
-#[derive(Debug)]
+impl AccountContributionTaxability {
+    // How an account is taxed when it isn't set explicitly.  Roth accounts have to be set
+    // explicitly, since post-tax contributions default to a taxable account.
+    fn tax_treatment(&self) -> AccountTaxTreatment {
+        match self {
+            AccountContributionTaxability::PreTax => AccountTaxTreatment::TaxDeferred,
+            AccountContributionTaxability::PostTax => AccountTaxTreatment::Taxable
+        }
+    }
+}
+
+// Which IRS limit, if any, contributions to an account count against
+#[derive(Copy,Clone,PartialEq,Eq,Debug)]
+#[wasm_bindgen]
+pub enum AccountContributionLimit {
+    Unlimited,
+    EmployerPlan,
+    Ira
+}
+
+// The employer matches rate of each slice of the employee's deferral (as a percentage of gross)
+// from floor up to the next tier's floor, e.g. 100% of the first 3% and 50% of the next 2% is
+// [{0.0, 1.0}, {0.03, 0.5}, {0.05, 0.0}]
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct MatchTier {
+    pub floor: f64,
+    pub rate: f64
+}
+
+simplifying_assumption!("Only employee contributions to accounts with an EmployerPlan limit are \
+    matched, and they're all matched by every employer account with a match formula.");
+#[derive(Clone,Debug,Default)]
+#[wasm_bindgen]
+pub struct EmployerMatch {
+    tiers: Vec<MatchTier>,
+    // Match on the whole year's deferrals at the end of the year, so front-loading deferrals
+    // doesn't lose out on the match once the limit is hit
+    true_up: bool
+}
+
+impl EmployerMatch {
+    pub fn new(tiers: Vec<MatchTier>, true_up: bool) -> EmployerMatch {
+        assert!(tiers.windows(2).all(|w| w[0].floor < w[1].floor));
+
+        EmployerMatch { tiers, true_up }
+    }
+
+    pub fn none() -> EmployerMatch {
+        Self::new(vec![], false)
+    }
+
+    // Match as a percentage of gross for the given employee deferral percentage
+    fn match_pct(&self, deferral_pct: f64) -> f64 {
+        let mut pct = 0.0;
+
+        for (i, tier) in self.tiers.iter().enumerate() {
+            if deferral_pct <= tier.floor {
+                break;
+            }
+
+            let ceil = match self.tiers.get(i + 1) {
+                Some(next) => f64::min(deferral_pct, next.floor),
+                None => deferral_pct
+            };
+            pct += (ceil - tier.floor) * tier.rate;
+        }
+
+        pct
+    }
+
+    fn match_for(&self, gross: f64, deferrals: f64) -> f64 {
+        if gross > 0.0 { gross * self.match_pct(deferrals / gross) } else { 0.0 }
+    }
+}
+
+#[wasm_bindgen]
+impl EmployerMatch {
+    #[wasm_bindgen(constructor)]
+    pub fn new_from_js(tier_floors: Vec<f64>, tier_rates: Vec<f64>, true_up: bool) -> EmployerMatch {
+        assert_eq!(tier_floors.len(), tier_rates.len());
+
+        let tiers = tier_floors.into_iter().zip(tier_rates)
+            .map(|(floor, rate)| { MatchTier{floor, rate} })
+            .collect();
+
+        Self::new(tiers, true_up)
+    }
+}
+
+simplifying_assumption!("Years of service start counting when the job starts in the simulation.");
+#[derive(Clone,Debug,Default)]
+pub enum Vesting {
+    #[default]
+    Immediate,
+    // Fully vested after this many years of service, nothing before
+    Cliff{years: usize},
+    // Vested percentage by completed years of service, fully vested after the end of the schedule
+    Graded{schedule: Vec<f64>}
+}
+
+impl Vesting {
+    fn vested_pct(&self, years_of_service: usize) -> f64 {
+        match self {
+            Vesting::Immediate => 1.0,
+            Vesting::Cliff { years } => if years_of_service >= *years { 1.0 } else { 0.0 },
+            Vesting::Graded { schedule } => *schedule.get(years_of_service).unwrap_or(&1.0)
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct VestingJS {
+    vesting: Vesting
+}
+
+#[wasm_bindgen]
+impl VestingJS {
+    #[wasm_bindgen]
+    pub fn immediate() -> VestingJS {
+        VestingJS{ vesting: Vesting::Immediate }
+    }
+
+    #[wasm_bindgen]
+    pub fn cliff(years: usize) -> VestingJS {
+        VestingJS{ vesting: Vesting::Cliff { years } }
+    }
+
+    #[wasm_bindgen]
+    pub fn graded(schedule: Vec<f64>) -> VestingJS {
+        VestingJS{ vesting: Vesting::Graded { schedule } }
+    }
+}
+
+#[derive(Clone,Debug)]
 #[wasm_bindgen]
 pub struct AccountContributionSettings {
     account: AccountSettings,
     contribution_pct: f64,
     contribution_source: AccountContributionSource,
-    tax: AccountContributionTaxability
+    tax: AccountContributionTaxability,
+    limit: AccountContributionLimit,
+    employer_match: EmployerMatch,
+    vesting: Vesting
 }
 
 #[derive(Debug)]
@@ -85,45 +274,184 @@ pub struct AccountContribution {
     account: Account,
     contribution_pct: f64,
     contribution_source: AccountContributionSource,
-    tax: AccountContributionTaxability
+    tax: AccountContributionTaxability,
+    limit: AccountContributionLimit,
+    employer_match: EmployerMatch,
+    vesting: Vesting,
+    match_this_year: f64
+}
+
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct CatchUpLimits {
+    pub age: usize,
+    pub elective_deferral: f64,
+    pub ira: f64
+}
+
+#[wasm_bindgen]
+impl CatchUpLimits {
+    #[wasm_bindgen(constructor)]
+    pub fn new(age: usize, elective_deferral: f64, ira: f64) -> CatchUpLimits {
+        CatchUpLimits { age, elective_deferral, ira }
+    }
+}
+
+// Annual limits, in today's dollars
+simplifying_assumption!("Indexed limits aren't rounded down to the IRS's $500 increments, and will \
+    go down in years with deflation.");
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct ContributionLimits {
+    elective_deferral: f64,
+    annual_additions: f64,
+    ira: f64,
+    catch_up: CatchUpLimits,
+    adjust_for_inflation: bool
 }
 
-#[derive(Debug)]
+#[wasm_bindgen]
+impl ContributionLimits {
+    #[wasm_bindgen(constructor)]
+    pub fn new(elective_deferral: f64, annual_additions: f64, ira: f64, catch_up: CatchUpLimits, adjust_for_inflation: bool) -> ContributionLimits {
+        ContributionLimits { elective_deferral, annual_additions, ira, catch_up, adjust_for_inflation }
+    }
+
+    #[wasm_bindgen]
+    pub fn irs_2023() -> ContributionLimits {
+        Self::new(22500.0, 66000.0, 6500.0, CatchUpLimits::new(50, 7500.0, 1000.0), true)
+    }
+}
+
+impl ContributionLimits {
+    fn room_for_year(&self, inflation: f64, catch_up: bool) -> ContributionRoom {
+        let inflation = if self.adjust_for_inflation { inflation } else { 1.0 };
+        let (elective_catch_up, ira_catch_up) = if catch_up { (self.catch_up.elective_deferral, self.catch_up.ira) } else { (0.0, 0.0) };
+
+        ContributionRoom {
+            elective_deferral: (self.elective_deferral + elective_catch_up) * inflation,
+            annual_additions: (self.annual_additions + elective_catch_up) * inflation,
+            ira: (self.ira + ira_catch_up) * inflation
+        }
+    }
+}
+
+// What's left of this year's limits
+#[derive(Copy,Clone,Debug)]
+struct ContributionRoom {
+    elective_deferral: f64,
+    annual_additions: f64,
+    ira: f64
+}
+
+impl ContributionRoom {
+    fn unlimited() -> ContributionRoom {
+        ContributionRoom { elective_deferral: f64::INFINITY, annual_additions: f64::INFINITY, ira: f64::INFINITY }
+    }
+
+    // Returns how much of the desired contribution fits under the limits, and uses up that much room
+    fn take(&mut self, limit: AccountContributionLimit, source: AccountContributionSource, desired: f64) -> f64 {
+        match (limit, source) {
+            (AccountContributionLimit::Unlimited, _) => desired,
+            (AccountContributionLimit::EmployerPlan, AccountContributionSource::Employee) => {
+                let allowed = desired.min(self.elective_deferral).min(self.annual_additions);
+                self.elective_deferral -= allowed;
+                self.annual_additions -= allowed;
+                allowed
+            },
+            (AccountContributionLimit::EmployerPlan, AccountContributionSource::Employer) => {
+                let allowed = desired.min(self.annual_additions);
+                self.annual_additions -= allowed;
+                allowed
+            },
+            (AccountContributionLimit::Ira, _) => {
+                let allowed = desired.min(self.ira);
+                self.ira -= allowed;
+                allowed
+            }
+        }
+    }
+}
+
+#[derive(Clone,Debug)]
 #[wasm_bindgen]
 pub struct JobSettings {
     // name, 401k, pension
     starting_gross_income: f64,
     fica: Fica,
     raise: RaiseSettings,
-    account_contribution_settings: Vec<AccountContributionSettings>
+    account_contribution_settings: Vec<AccountContributionSettings>,
+    contribution_limits: Option<ContributionLimits>,
+    overflow_account: Option<usize>,
+    pension: Option<PensionSettings>
 }
 
 #[derive(Debug)]
 pub struct Job {
     starting_gross_income: f64,
     gross_income: Vec<f64>,
+    // Gross income up to the OASDI wage base
+    covered_earnings: Vec<f64>,
     net_income: Vec<f64>,
     fica: Fica,
     raise: RaiseSettings,
     rates: Rc<Vec<Rate>>,
-    account_contributions: Vec<AccountContribution>
+    account_contributions: Vec<AccountContribution>,
+    contribution_limits: Option<ContributionLimits>,
+    contribution_room: ContributionRoom,
+    overflow_account: Option<usize>,
+    starting_age_months: usize,
+    gross_this_year: f64,
+    plan_deferrals_this_year: f64,
+    last_period_worked: Option<Period>,
+    pension: Option<PensionSettings>,
+    // Periods the job is held, from start up to but not including end
+    start: usize,
+    end: usize
 }
 
 #[wasm_bindgen]
 impl AccountContributionSettings {
     #[wasm_bindgen(constructor)]
     pub fn new(account: AccountSettings, contribution_pct: f64, contribution_source: AccountContributionSource, tax: AccountContributionTaxability) -> AccountContributionSettings {
-        AccountContributionSettings { account, contribution_pct, contribution_source, tax }
+        Self::new_with_limit(account, contribution_pct, contribution_source, tax, AccountContributionLimit::Unlimited)
+    }
+
+    #[wasm_bindgen]
+    pub fn new_with_limit(account: AccountSettings, contribution_pct: f64, contribution_source: AccountContributionSource, tax: AccountContributionTaxability, limit: AccountContributionLimit) -> AccountContributionSettings {
+        AccountContributionSettings { account, contribution_pct, contribution_source, tax, limit, employer_match: EmployerMatch::none(), vesting: Vesting::Immediate }
+    }
+
+    // Employer contributions of nonelective_pct of gross, plus a match on the employee's deferrals
+    #[wasm_bindgen]
+    pub fn new_employer_from_js(account: AccountSettings, nonelective_pct: f64, employer_match: EmployerMatch, limit: AccountContributionLimit, vesting: VestingJS) -> AccountContributionSettings {
+        Self::new_employer(account, nonelective_pct, employer_match, limit, vesting.vesting)
     }
 }
 
 impl AccountContributionSettings {
+    pub fn new_employer(account: AccountSettings, nonelective_pct: f64, employer_match: EmployerMatch, limit: AccountContributionLimit, vesting: Vesting) -> AccountContributionSettings {
+        AccountContributionSettings {
+            account,
+            contribution_pct: nonelective_pct,
+            contribution_source: AccountContributionSource::Employer,
+            tax: AccountContributionTaxability::PreTax,
+            limit,
+            employer_match,
+            vesting
+        }
+    }
+
     pub fn create_account_contribution(&self, lifespan: Lifespan, rates: Rc<Vec<Rate>>) -> AccountContribution {
         AccountContribution {
-            account: self.account.create_account(lifespan, rates),
+            account: self.account.with_default_tax_treatment(self.tax.tax_treatment()).create_account(lifespan, rates),
             contribution_pct: self.contribution_pct,
             contribution_source: self.contribution_source,
-            tax: self.tax
+            tax: self.tax,
+            limit: self.limit,
+            employer_match: self.employer_match.clone(),
+            vesting: self.vesting.clone(),
+            match_this_year: 0.0
         }
     }
 }
@@ -146,40 +474,162 @@ impl AccountContributionSettingsVec {
     }
 }
 
+// Only deferrals to the employer plan are matched, so a match formula with nothing to match would
+// silently never pay out
+fn assert_match_has_deferrals(account_contribution_settings: &[AccountContributionSettings]) {
+    let matched = account_contribution_settings.iter().any(|a| !a.employer_match.tiers.is_empty());
+    let deferrals = account_contribution_settings.iter()
+        .any(|a| a.contribution_source == AccountContributionSource::Employee && a.limit == AccountContributionLimit::EmployerPlan);
+
+    assert!(!matched || deferrals, "an employer match needs an employee account with an EmployerPlan limit to match");
+}
+
 #[wasm_bindgen]
 impl JobSettings {
     #[wasm_bindgen(constructor)]
     pub fn new_from_js(starting_gross_income: f64, fica: FicaJS, raise: RaiseSettings, account_contribution_settings: AccountContributionSettingsVec) -> JobSettings {
         Self::new(starting_gross_income, fica.fica, raise, account_contribution_settings.vec)
     }
+
+    // overflow_account is the index into account_contribution_settings that contributions over the
+    // limits spill into
+    #[wasm_bindgen]
+    pub fn new_with_limits_from_js(starting_gross_income: f64, fica: FicaJS, raise: RaiseSettings, account_contribution_settings: AccountContributionSettingsVec, contribution_limits: ContributionLimits, overflow_account: Option<usize>) -> JobSettings {
+        Self::new_with_limits(starting_gross_income, fica.fica, raise, account_contribution_settings.vec, contribution_limits, overflow_account)
+    }
+
+    #[wasm_bindgen]
+    pub fn with_pension(self, pension: PensionSettings) -> JobSettings {
+        JobSettings { pension: Some(pension), ..self }
+    }
 }
 
 impl JobSettings {
     pub fn new(starting_gross_income: f64, fica: Fica, raise: RaiseSettings, account_contribution_settings: Vec<AccountContributionSettings>) -> JobSettings {
-        JobSettings { starting_gross_income, fica, raise, account_contribution_settings }
+        assert_match_has_deferrals(&account_contribution_settings);
+
+        JobSettings { starting_gross_income, fica, raise, account_contribution_settings, contribution_limits: None, overflow_account: None, pension: None }
+    }
+
+    pub fn new_with_limits(starting_gross_income: f64, fica: Fica, raise: RaiseSettings, account_contribution_settings: Vec<AccountContributionSettings>, contribution_limits: ContributionLimits, overflow_account: Option<usize>) -> JobSettings {
+        if let Some(overflow_account) = overflow_account {
+            let overflow = &account_contribution_settings[overflow_account];
+            assert_eq!(overflow.limit, AccountContributionLimit::Unlimited);
+            assert_eq!(overflow.tax, AccountContributionTaxability::PostTax);
+        }
+        assert_match_has_deferrals(&account_contribution_settings);
+
+        JobSettings { starting_gross_income, fica, raise, account_contribution_settings, contribution_limits: Some(contribution_limits), overflow_account, pension: None }
+    }
+
+    pub fn pension(&self) -> Option<PensionSettings> {
+        self.pension
+    }
+
+    pub fn create_job(&self, lifespan: Lifespan, careerspan: Lifespan, starting_age_months: usize, rates: Rc<Vec<Rate>>) -> Job {
+        self.create_job_between(lifespan, careerspan, 0, careerspan.periods(), starting_age_months, rates)
     }
 
-    pub fn create_job(&self, lifespan: Lifespan, careerspan: Lifespan, rates: Rc<Vec<Rate>>) -> Job {
+    // A job held from the start period up to but not including the end period
+    pub fn create_job_between(&self, lifespan: Lifespan, careerspan: Lifespan, start: usize, end: usize, starting_age_months: usize, rates: Rc<Vec<Rate>>) -> Job {
         assert_eq!(lifespan.periods(), rates.len());
+        assert!(end <= careerspan.periods());
         let gross_income = vec![0.0; careerspan.periods()];
         let net_income = vec![0.0; careerspan.periods()];
         let account_contributions = self.account_contribution_settings.iter().map(|settings| settings.create_account_contribution(lifespan, rates.clone()) ).collect();
 
-        Job { starting_gross_income: self.starting_gross_income, gross_income, net_income, fica: self.fica, raise: self.raise, rates, account_contributions }
+        Job {
+            starting_gross_income: self.starting_gross_income,
+            gross_income,
+            covered_earnings: vec![0.0; careerspan.periods()],
+            net_income,
+            fica: self.fica,
+            raise: self.raise,
+            rates,
+            account_contributions,
+            contribution_limits: self.contribution_limits,
+            contribution_room: ContributionRoom::unlimited(),
+            overflow_account: self.overflow_account,
+            starting_age_months,
+            gross_this_year: 0.0,
+            plan_deferrals_this_year: 0.0,
+            last_period_worked: None,
+            pension: self.pension,
+            start,
+            end
+        }
+    }
+}
+
+impl Job {
+    fn is_working(&self, period: Period) -> bool {
+        (self.start..self.end).contains(&period.get())
+    }
+
+    // Keeps the accounts growing in periods after the job ends.  Before it starts, their
+    // starting balances are just carried forward.
+    fn invest_accounts(&mut self, period: Period) {
+        for account in self.account_contributions.iter_mut() {
+            if period.get() < self.start {
+                account.account.carry_over_next_period(period);
+            } else {
+                account.account.rebalance_and_invest_next_period(period);
+            }
+        }
+    }
+
+    // Unvested employer money is forfeited when the job ends before retirement
+    fn leave(&mut self) {
+        if let Some(period) = self.last_period_worked {
+            for account in &mut self.account_contributions {
+                account.account.forfeit_unvested(period);
+            }
+        }
+    }
+
+    // Rolls the tax-advantaged accounts over into the rollover settings' allocation and fees.
+    // Taxable accounts stay where they are.
+    fn roll_over(&mut self, rollover: &AccountSettings) {
+        for account in &mut self.account_contributions {
+            if account.account.tax_treatment() != AccountTaxTreatment::Taxable {
+                account.account.roll_over(rollover);
+            }
+        }
+    }
+
+    fn start_new_year(&mut self, period: Period) {
+        let year = period.round_down_to_year();
+
+        self.gross_this_year = 0.0;
+        self.plan_deferrals_this_year = 0.0;
+        for account in &mut self.account_contributions {
+            account.match_this_year = 0.0;
+        }
+
+        self.contribution_room = match &self.contribution_limits {
+            Some(limits) => {
+                // Catch-up contributions are allowed for the whole year you hit the catch-up age
+                let last_month = year + 11;
+                let age_at_year_end = (self.starting_age_months + last_month.get()) / 12;
+                limits.room_for_year(cumulative_inflation(&self.rates, year), age_at_year_end >= limits.catch_up.age)
+            },
+            None => ContributionRoom::unlimited()
+        };
     }
 }
 
 impl IncomeProvider for Job {
     fn calculate_income_for_period(&mut self, period: Period, tax: &mut impl TaxCollector) {
-        assert!(period.get() < self.net_income.len());
+        assert!(self.is_working(period));
 
         // Rebalance + invest for this period.  This has to be done before we deposit anything
         for account in self.account_contributions.iter_mut() {
             account.account.rebalance_and_invest_next_period(period);
         }
 
-        let gross = if period.get() == 0 {
-            self.starting_gross_income
+        // Starting pay is in today's dollars, for jobs that start later on
+        let gross = if period.get() == self.start {
+            self.starting_gross_income * cumulative_inflation(&self.rates, period)
         } else if !period.is_new_year() {
             self.gross_income[period.get() - 1]
         } else {
@@ -197,18 +647,38 @@ impl IncomeProvider for Job {
 
         self.gross_income[period.get()] = gross;
 
+        if period.get() == self.start || period.is_new_year() {
+            self.start_new_year(period);
+        }
+
+        // The wage base and Additional Medicare Tax threshold apply to the whole year's wages
         let fica_deduction = match self.fica {
-            Fica::Participant { ss_rate } => { gross * ss_rate },
+            Fica::Participant(settings) => {
+                let inflation = cumulative_inflation(&self.rates, period.round_down_to_year());
+                self.covered_earnings[period.get()] = settings.covered_wages(self.gross_this_year, gross, inflation);
+                settings.withholding(self.gross_this_year, gross, inflation)
+            },
             Fica::Exempt => { 0.0 }
         };
 
+        // Employee contributions that didn't fit under the limits
+        let mut excess_contributions = 0.0;
+
+        // Deferrals to the employer plan, which the employer matches
+        let mut plan_deferrals = 0.0;
+
         let mut pretax_contributions = 0.0;
         for account in &mut self.account_contributions {
-            if account.tax == AccountContributionTaxability::PreTax {
-                if account.contribution_source == AccountContributionSource::Employee {
-                    pretax_contributions += gross * account.contribution_pct
+            if account.tax == AccountContributionTaxability::PreTax && account.contribution_source == AccountContributionSource::Employee {
+                let desired = gross * account.contribution_pct;
+                let allowed = self.contribution_room.take(account.limit, account.contribution_source, desired);
+
+                pretax_contributions += allowed;
+                excess_contributions += desired - allowed;
+                if account.limit == AccountContributionLimit::EmployerPlan {
+                    plan_deferrals += allowed;
                 }
-                account.account.deposit(gross * account.contribution_pct, period);
+                account.account.deposit(allowed, period);
             }
         }
 
@@ -216,27 +686,87 @@ impl IncomeProvider for Job {
 
         let net = tax.collect_income_taxes(Money::Taxable(taxable), period).leftover();
 
-        // TODO contribute to social security/pension
+        // TODO contribute to pension
 
         let mut posttax_contributions = 0.0;
         for account in &mut self.account_contributions {
             if account.tax == AccountContributionTaxability::PostTax {
                 assert_eq!(account.contribution_source, AccountContributionSource::Employee);
 
-                posttax_contributions += gross * account.contribution_pct;
-                account.account.deposit(gross * account.contribution_pct, period);
+                let desired = gross * account.contribution_pct;
+                let allowed = self.contribution_room.take(account.limit, account.contribution_source, desired);
+
+                posttax_contributions += allowed;
+                excess_contributions += desired - allowed;
+                if account.limit == AccountContributionLimit::EmployerPlan {
+                    plan_deferrals += allowed;
+                }
+                account.account.deposit(allowed, period);
+            }
+        }
+
+        self.gross_this_year += gross;
+        self.plan_deferrals_this_year += plan_deferrals;
+        let years_of_service = (period.get() - self.start + 1) / 12;
+
+        for account in &mut self.account_contributions {
+            if account.contribution_source == AccountContributionSource::Employer {
+                let nonelective = self.contribution_room.take(account.limit, account.contribution_source, gross * account.contribution_pct);
+
+                let mut employer_match = account.employer_match.match_for(gross, plan_deferrals);
+                // Trued up at the end of each year, and when leaving the job
+                let year_end = (period + 1).is_new_year() || period.get() + 1 == self.end;
+                if account.employer_match.true_up && year_end {
+                    let annual_match = account.employer_match.match_for(self.gross_this_year, self.plan_deferrals_this_year);
+                    employer_match = f64::max(employer_match, annual_match - account.match_this_year);
+                }
+                let employer_match = self.contribution_room.take(account.limit, account.contribution_source, employer_match);
+                account.match_this_year += employer_match;
+
+                account.account.deposit_employer(nonelective + employer_match, period);
             }
+
+            account.account.set_vested_pct(account.vesting.vested_pct(years_of_service), period);
+        }
+
+        // Anything over the limits is invested in the overflow account instead, if there is one.
+        // Otherwise it's just kept as take-home pay.
+        if let Some(overflow_account) = self.overflow_account {
+            posttax_contributions += excess_contributions;
+            self.account_contributions[overflow_account].account.deposit(excess_contributions, period);
         }
 
         self.net_income[period.get()] = net - fica_deduction - posttax_contributions;
+        self.last_period_worked = Some(period);
     }
 
     fn get_net_income(&self) -> &Vec<f64> {
         &self.net_income
     }
 
-    fn retire(self) -> (f64, Vec<Account>) {
+    fn covered_earnings(&self) -> &[f64] {
+        &self.covered_earnings
+    }
+
+    fn pensions(&self) -> Vec<Pension> {
+        let (settings, last_period_worked) = match (self.pension, self.last_period_worked) {
+            (Some(settings), Some(period)) => (settings, period),
+            _ => return vec![]
+        };
+
+        // Pensions from jobs that ended early still only start at retirement
+        let retirement = self.gross_income.len();
+        vec![settings.create_pension(&self.gross_income[self.start..=last_period_worked.get()], retirement, self.starting_age_months + retirement)]
+    }
+
+    fn retire(mut self) -> (f64, Vec<Account>) {
         let months = std::cmp::min(12, self.net_income.len());
+        // Plans fully vest at retirement
+        if let Some(period) = self.last_period_worked {
+            for account in &mut self.account_contributions {
+                account.account.set_vested_pct(1.0, period);
+            }
+        }
 
         (
             self.net_income[self.net_income.len()-months..].iter().sum::<f64>() / (months as f64),
@@ -244,8 +774,184 @@ impl IncomeProvider for Job {
         )
     }
 
-    fn account_contributions(&self) -> &[AccountContribution] {
-        &self.account_contributions
+    fn pay_expense(&mut self, amount: f64, period: Period) -> f64 {
+        pay_expense_pro_rata(amount, self.account_contributions.iter_mut().map(|a| &mut a.account).collect(), period)
+    }
+}
+
+fn pay_expense_pro_rata(amount: f64, mut accounts: Vec<&mut Account>, period: Period) -> f64 {
+    simplifying_assumption!("Lump sums paid before retirement come out of all accounts pro rata, \
+        without taxes or early withdrawal penalties.");
+    let total: f64 = accounts.iter().map(|a| a.balance()[period.get()]).sum();
+    if total <= 0.0 {
+        return amount;
+    }
+
+    let covered = f64::min(amount, total);
+    for account in &mut accounts {
+        let share = account.balance()[period.get()] / total;
+        account.withdraw_from_period(share * covered, period);
+    }
+
+    amount - covered
+}
+
+// A job held from start_age_months until end_age_months, or until retirement if there's no end.
+// Tax-advantaged accounts are rolled over into the allocation and fees of the rollover settings
+// when the job ends, otherwise they stay with the old employer and keep growing.
+#[derive(Clone,Debug)]
+#[wasm_bindgen]
+pub struct CareerJobSettings {
+    job: JobSettings,
+    start_age_months: usize,
+    end_age_months: Option<usize>,
+    rollover: Option<AccountSettings>
+}
+
+#[wasm_bindgen]
+impl CareerJobSettings {
+    #[wasm_bindgen(constructor)]
+    pub fn new(job: JobSettings, start_age_months: usize, end_age_months: Option<usize>) -> CareerJobSettings {
+        if let Some(end_age_months) = end_age_months {
+            assert!(start_age_months < end_age_months);
+        }
+
+        CareerJobSettings { job, start_age_months, end_age_months, rollover: None }
+    }
+
+    #[wasm_bindgen]
+    pub fn with_rollover(self, rollover: AccountSettings) -> CareerJobSettings {
+        CareerJobSettings { rollover: Some(rollover), ..self }
+    }
+}
+
+// Jobs can overlap, or leave gaps with no income in between
+simplifying_assumption!("Contribution limits and the OASDI wage base are applied to each job on \
+    its own, so concurrent jobs can each contribute and withhold up to the full limits.");
+#[derive(Clone,Debug,Default)]
+#[wasm_bindgen]
+pub struct CareerSettings {
+    jobs: Vec<CareerJobSettings>
+}
+
+#[wasm_bindgen]
+impl CareerSettings {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> CareerSettings {
+        CareerSettings { jobs: vec![] }
+    }
+
+    #[wasm_bindgen]
+    pub fn add(&mut self, job: CareerJobSettings) {
+        self.jobs.push(job);
+    }
+}
+
+impl CareerSettings {
+    pub fn create_career(&self, lifespan: Lifespan, careerspan: Lifespan, starting_age_months: usize, rates: Rc<Vec<Rate>>) -> Career {
+        let period_at_age = |age_months: usize| usize::min(age_months.saturating_sub(starting_age_months), careerspan.periods());
+
+        let jobs = self.jobs.iter().map(|settings| {
+            let start = period_at_age(settings.start_age_months);
+            let end = settings.end_age_months.map_or(careerspan.periods(), period_at_age);
+            settings.job.create_job_between(lifespan, careerspan, start, end, starting_age_months, Rc::clone(&rates))
+        }).collect();
+
+        Career {
+            jobs,
+            rollovers: self.jobs.iter().map(|settings| settings.rollover.clone()).collect(),
+            net_income: vec![0.0; careerspan.periods()],
+            covered_earnings: vec![0.0; careerspan.periods()],
+            lifespan
+        }
+    }
+
+    // Whether any job's pension offers a lump sum instead of the annuity
+    pub fn offers_lump_sum(&self) -> bool {
+        self.jobs.iter().any(|settings| settings.job.pension().and_then(|pension| pension.lump_sum()).is_some())
+    }
+
+    // The same career, taking every pension as an annuity
+    pub fn declining_lump_sums(&self) -> CareerSettings {
+        let jobs = self.jobs.iter().map(|settings| match settings.job.pension() {
+            Some(pension) => CareerJobSettings { job: settings.job.clone().with_pension(pension.declining_lump_sum()), ..settings.clone() },
+            None => settings.clone()
+        }).collect();
+
+        CareerSettings { jobs }
+    }
+}
+
+// The whole career is just the one job
+impl From<JobSettings> for CareerSettings {
+    fn from(job: JobSettings) -> CareerSettings {
+        CareerSettings { jobs: vec![CareerJobSettings::new(job, 0, None)] }
+    }
+}
+
+#[derive(Debug)]
+pub struct Career {
+    jobs: Vec<Job>,
+    rollovers: Vec<Option<AccountSettings>>,
+    net_income: Vec<f64>,
+    covered_earnings: Vec<f64>,
+    lifespan: Lifespan
+}
+
+impl IncomeProvider for Career {
+    fn calculate_income_for_period(&mut self, period: Period, tax: &mut impl TaxCollector) {
+        // Jobs that ended last period are left before anything is invested for this one
+        for (job, rollover) in self.jobs.iter_mut().zip(&self.rollovers) {
+            if period.get() == job.end {
+                job.leave();
+                if let Some(rollover) = rollover {
+                    job.roll_over(rollover);
+                }
+            }
+        }
+
+        for job in &mut self.jobs {
+            if job.is_working(period) {
+                job.calculate_income_for_period(period, &mut *tax);
+                self.net_income[period.get()] += job.net_income[period.get()];
+                self.covered_earnings[period.get()] += job.covered_earnings[period.get()];
+            } else {
+                job.invest_accounts(period);
+            }
+        }
+    }
+
+    fn get_net_income(&self) -> &Vec<f64> {
+        &self.net_income
+    }
+
+    fn covered_earnings(&self) -> &[f64] {
+        &self.covered_earnings
+    }
+
+    fn pensions(&self) -> Vec<Pension> {
+        self.jobs.iter().flat_map(|job| job.pensions()).collect()
+    }
+
+    fn retire(self) -> (f64, Vec<Account>) {
+        let mut pre_retirement_income = 0.0;
+        let mut accounts = vec![];
+
+        for job in self.jobs {
+            let (income, job_accounts) = job.retire();
+            pre_retirement_income += income;
+            accounts.extend(job_accounts);
+        }
+
+        (pre_retirement_income, accounts)
+    }
+
+    fn pay_expense(&mut self, amount: f64, period: Period) -> f64 {
+        let accounts = self.jobs.iter_mut()
+            .flat_map(|job| job.account_contributions.iter_mut().map(|a| &mut a.account))
+            .collect();
+
+        pay_expense_pro_rata(amount, accounts, period)
     }
 }
 
@@ -254,10 +960,15 @@ mod tests {
     use assert_float_eq::*;
 
     use super::*;
-    use crate::assets::AssetAllocation;
+    use crate::assets::{AssetAllocation, FeeSettings};
     use crate::util::tests::assert_vecfloat_absolute;
     use crate::taxes::{MockTaxCollector,TaxResult};
 
+    // Fica without a wage base or Medicare
+    fn flat_fica(rate: f64) -> Fica {
+        Fica::Participant(FicaSettings::new(rate, f64::INFINITY, 0.0, 0.0, f64::INFINITY, false))
+    }
+
     fn get_tax_mock(rate: f64) -> impl TaxCollector {
         let mut mock = MockTaxCollector::default();
         mock.expect_collect_income_taxes().returning(move |money, _period| {
@@ -274,7 +985,7 @@ mod tests {
         let job_settings = JobSettings::new(1000.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![] );
         let lifespan = Lifespan::new(16);
         let rates = vec![Rate::new(1.0, 1.0, 1.0); 16];
-        let mut job = job_settings.create_job(lifespan, lifespan, Rc::new(rates));
+        let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates));
         let mut tax = get_tax_mock(0.0);
 
         for period in lifespan.iter() {
@@ -289,7 +1000,7 @@ mod tests {
         let job_settings = JobSettings::new(1024.0, Fica::Exempt, RaiseSettings { amount: 1.0625, adjust_for_inflation: false }, vec![] );
         let lifespan = Lifespan::new(16);
         let rates = vec![Rate::new(1.0, 1.0, 1.0); 16];
-        let mut job = job_settings.create_job(lifespan, lifespan, Rc::new(rates));
+        let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates));
         let mut tax = get_tax_mock(0.0);
 
         for period in lifespan.iter() {
@@ -304,7 +1015,7 @@ mod tests {
         let job_settings = JobSettings::new(1024.0, Fica::Exempt, RaiseSettings { amount: 1.0625, adjust_for_inflation: true }, vec![] );
         let lifespan = Lifespan::new(16);
         let rates = vec![Rate::new(1.0, 1.0, 1.002); 16];
-        let mut job = job_settings.create_job(lifespan, lifespan, Rc::new(rates));
+        let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates));
         let mut tax = get_tax_mock(0.0);
 
         for period in lifespan.iter() {
@@ -316,10 +1027,10 @@ mod tests {
 
     #[test]
     pub fn calculateincome_fica_raiseinflation_notax() {
-        let job_settings = JobSettings::new(1024.0, Fica::Participant { ss_rate: 0.0625 }, RaiseSettings { amount: 1.0625, adjust_for_inflation: true }, vec![] );
+        let job_settings = JobSettings::new(1024.0, flat_fica(0.0625), RaiseSettings { amount: 1.0625, adjust_for_inflation: true }, vec![] );
         let lifespan = Lifespan::new(16);
         let rates = vec![Rate::new(1.0, 1.0, 1.002); 16];
-        let mut job = job_settings.create_job(lifespan, lifespan, Rc::new(rates));
+        let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates));
         let mut tax = get_tax_mock(0.0);
 
         for period in lifespan.iter() {
@@ -329,12 +1040,43 @@ mod tests {
         assert_vecfloat_absolute(job.get_net_income().clone(), vec![960.0, 960.0, 960.0, 960.0, 960.0, 960.0, 960.0, 960.0, 960.0, 960.0, 960.0, 960.0, 1044.751083304, 1044.751083304, 1044.751083304, 1044.751083304]);
     }
 
+    #[test]
+    pub fn ficasettings_withholding() {
+        let settings = FicaSettings::new(0.062, 100000.0, 0.0145, 0.009, 150000.0, true);
+
+        assert_float_absolute_eq!(settings.withholding(0.0, 10000.0, 1.0), 765.0);
+        // Crossing the wage base
+        assert_float_absolute_eq!(settings.withholding(95000.0, 10000.0, 1.0), 455.0);
+        // Crossing the Additional Medicare Tax threshold
+        assert_float_absolute_eq!(settings.withholding(145000.0, 10000.0, 1.0), 190.0);
+        // The wage base goes up with inflation, but the threshold doesn't
+        assert_float_absolute_eq!(settings.withholding(195000.0, 10000.0, 2.0), 545.0);
+    }
+
+    #[test]
+    pub fn calculateincome_ficawagebase_notax() {
+        let fica = Fica::Participant(FicaSettings::new(0.1, 25000.0, 0.0, 0.0, f64::INFINITY, false));
+        let job_settings = JobSettings::new(10000.0, fica, RaiseSettings { amount: 1.0, adjust_for_inflation: false }, vec![] );
+        let lifespan = Lifespan::new(16);
+        let rates = vec![Rate::new(1.0, 1.0, 1.0); 16];
+        let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates));
+        let mut tax = get_tax_mock(0.0);
+
+        for period in lifespan.iter() {
+            job.calculate_income_for_period(period, &mut tax);
+        }
+
+        // The wage base is hit in the third month, and resets with the new year
+        assert_vecfloat_absolute(job.get_net_income().clone(), vec![9000.0, 9000.0, 9500.0, 10000.0, 10000.0, 10000.0, 10000.0, 10000.0, 10000.0, 10000.0, 10000.0, 10000.0, 9000.0, 9000.0, 9500.0, 10000.0]);
+        assert_eq!(job.covered_earnings()[..4], [10000.0, 10000.0, 5000.0, 0.0]);
+    }
+
     #[test]
     pub fn calculateincome_noraise_10tax() {
         let job_settings = JobSettings::new(1000.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![] );
         let lifespan = Lifespan::new(16);
         let rates = vec![Rate::new(1.0, 1.0, 1.0); 16];
-        let mut job = job_settings.create_job(lifespan, lifespan, Rc::new(rates));
+        let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates));
         let mut tax = get_tax_mock(0.1);
 
         for period in lifespan.iter() {
@@ -346,10 +1088,10 @@ mod tests {
 
     #[test]
     pub fn calculateincome_fica_raise_10tax() {
-        let job_settings = JobSettings::new(1000.0, Fica::Participant { ss_rate: 0.0625 }, RaiseSettings {amount: 1.0625, adjust_for_inflation: true}, vec![] );
+        let job_settings = JobSettings::new(1000.0, flat_fica(0.0625), RaiseSettings {amount: 1.0625, adjust_for_inflation: true}, vec![] );
         let lifespan = Lifespan::new(16);
         let rates = vec![Rate::new(1.0, 1.0, 1.002); 16];
-        let mut job = job_settings.create_job(lifespan, lifespan, Rc::new(rates));
+        let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates));
         let mut tax = get_tax_mock(0.1);
 
         for period in lifespan.iter() {
@@ -363,11 +1105,11 @@ mod tests {
     pub fn calculateincome_fica_raise_10tax_employeepretax401k() {
         let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.5, 1, 0.5));
         let account = AccountSettings::new(0.0, asset_allocation);
-        let account_contributions = AccountContributionSettings { account, contribution_pct: 0.08, contribution_source: AccountContributionSource::Employee, tax: AccountContributionTaxability::PreTax };
-        let job_settings = JobSettings::new(1000.0, Fica::Participant { ss_rate: 0.0625 }, RaiseSettings {amount: 1.0625, adjust_for_inflation: true}, vec![account_contributions] );
+        let account_contributions = AccountContributionSettings { account, contribution_pct: 0.08, contribution_source: AccountContributionSource::Employee, tax: AccountContributionTaxability::PreTax, limit: AccountContributionLimit::Unlimited, employer_match: EmployerMatch::none(), vesting: Vesting::Immediate };
+        let job_settings = JobSettings::new(1000.0, flat_fica(0.0625), RaiseSettings {amount: 1.0625, adjust_for_inflation: true}, vec![account_contributions] );
         let lifespan = Lifespan::new(16);
         let rates = vec![Rate::new(1.006, 1.0, 1.002); 16];
-        let mut job = job_settings.create_job(lifespan, lifespan, Rc::new(rates));
+        let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates));
         let mut tax = get_tax_mock(0.1);
 
         for period in lifespan.iter() {
@@ -382,11 +1124,11 @@ mod tests {
     pub fn calculateincome_fica_raise_10tax_employerpretax401k() {
         let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.5, 1, 0.5));
         let account = AccountSettings::new(0.0, asset_allocation);
-        let account_contributions = AccountContributionSettings { account, contribution_pct: 0.08, contribution_source: AccountContributionSource::Employer, tax: AccountContributionTaxability::PreTax };
-        let job_settings = JobSettings::new(1000.0, Fica::Participant { ss_rate: 0.0625 }, RaiseSettings {amount: 1.0625, adjust_for_inflation: true}, vec![account_contributions] );
+        let account_contributions = AccountContributionSettings { account, contribution_pct: 0.08, contribution_source: AccountContributionSource::Employer, tax: AccountContributionTaxability::PreTax, limit: AccountContributionLimit::Unlimited, employer_match: EmployerMatch::none(), vesting: Vesting::Immediate };
+        let job_settings = JobSettings::new(1000.0, flat_fica(0.0625), RaiseSettings {amount: 1.0625, adjust_for_inflation: true}, vec![account_contributions] );
         let lifespan = Lifespan::new(16);
         let rates = vec![Rate::new(1.006, 1.0, 1.002); 16];
-        let mut job = job_settings.create_job(lifespan, lifespan, Rc::new(rates));
+        let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates));
         let mut tax = get_tax_mock(0.1);
 
         for period in lifespan.iter() {
@@ -402,11 +1144,11 @@ mod tests {
     pub fn calculateincome_fica_raise_10tax_employeeposttax401k() {
         let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.5, 1, 0.5));
         let account = AccountSettings::new(0.0, asset_allocation);
-        let account_contributions = AccountContributionSettings { account, contribution_pct: 0.08, contribution_source: AccountContributionSource::Employee, tax: AccountContributionTaxability::PostTax };
-        let job_settings = JobSettings::new(1000.0, Fica::Participant { ss_rate: 0.0625 }, RaiseSettings {amount: 1.0625, adjust_for_inflation: true}, vec![account_contributions] );
+        let account_contributions = AccountContributionSettings { account, contribution_pct: 0.08, contribution_source: AccountContributionSource::Employee, tax: AccountContributionTaxability::PostTax, limit: AccountContributionLimit::Unlimited, employer_match: EmployerMatch::none(), vesting: Vesting::Immediate };
+        let job_settings = JobSettings::new(1000.0, flat_fica(0.0625), RaiseSettings {amount: 1.0625, adjust_for_inflation: true}, vec![account_contributions] );
         let lifespan = Lifespan::new(16);
         let rates = vec![Rate::new(1.006, 1.0, 1.002); 16];
-        let mut job = job_settings.create_job(lifespan, lifespan, Rc::new(rates));
+        let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates));
         let mut tax = get_tax_mock(0.1);
 
         for period in lifespan.iter() {
@@ -417,16 +1159,96 @@ mod tests {
         assert_vecfloat_absolute(job.account_contributions[0].account.balance().to_vec(), vec![80.0, 160.24, 240.72072, 321.44288216, 402.40721080648, 483.614432438899, 565.065275736216, 646.760471563425, 728.700752978115, 810.886855237049, 893.31951580276, 975.999474350168, 1065.99006304858, 1156.25062351308, 1246.78196565898, 1337.58490183132]);
     }
 
+    #[test]
+    pub fn contributionlimits_roomforyear() {
+        let limits = ContributionLimits::new(1000.0, 3000.0, 500.0, CatchUpLimits::new(50, 250.0, 100.0), true);
+
+        let room = limits.room_for_year(1.0, false);
+        assert_eq!((room.elective_deferral, room.annual_additions, room.ira), (1000.0, 3000.0, 500.0));
+
+        let room = limits.room_for_year(1.5, true);
+        assert_eq!((room.elective_deferral, room.annual_additions, room.ira), (1875.0, 4875.0, 900.0));
+
+        let limits = ContributionLimits::new(1000.0, 3000.0, 500.0, CatchUpLimits::new(50, 250.0, 100.0), false);
+        let room = limits.room_for_year(1.5, false);
+        assert_eq!((room.elective_deferral, room.annual_additions, room.ira), (1000.0, 3000.0, 500.0));
+    }
+
+    #[test]
+    pub fn contributionroom_take() {
+        let mut room = ContributionRoom { elective_deferral: 1000.0, annual_additions: 1500.0, ira: 500.0 };
+
+        assert_eq!(room.take(AccountContributionLimit::EmployerPlan, AccountContributionSource::Employee, 750.0), 750.0);
+        assert_eq!(room.take(AccountContributionLimit::EmployerPlan, AccountContributionSource::Employer, 500.0), 500.0);
+        // Only 250 of annual additions left
+        assert_eq!(room.take(AccountContributionLimit::EmployerPlan, AccountContributionSource::Employee, 500.0), 250.0);
+        assert_eq!(room.take(AccountContributionLimit::EmployerPlan, AccountContributionSource::Employer, 500.0), 0.0);
+
+        assert_eq!(room.take(AccountContributionLimit::Ira, AccountContributionSource::Employee, 400.0), 400.0);
+        assert_eq!(room.take(AccountContributionLimit::Ira, AccountContributionSource::Employee, 400.0), 100.0);
+
+        assert_eq!(room.take(AccountContributionLimit::Unlimited, AccountContributionSource::Employee, 1e9), 1e9);
+    }
+
+    #[test]
+    pub fn calculateincome_10tax_electivedeferrallimit_overflow() {
+        let asset_allocation = Rc::new(AssetAllocation::new(vec![0.5]));
+        let plan = AccountContributionSettings::new_with_limit(AccountSettings::new(0.0, Rc::clone(&asset_allocation)), 0.5, AccountContributionSource::Employee, AccountContributionTaxability::PreTax, AccountContributionLimit::EmployerPlan);
+        let brokerage = AccountContributionSettings::new(AccountSettings::new(0.0, asset_allocation), 0.0, AccountContributionSource::Employee, AccountContributionTaxability::PostTax);
+        let limits = ContributionLimits::new(3000.0, 100000.0, 0.0, CatchUpLimits::new(50, 0.0, 0.0), false);
+        let job_settings = JobSettings::new_with_limits(1000.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![plan, brokerage], limits, Some(1));
+        let lifespan = Lifespan::new(16);
+        let rates = vec![Rate::new(1.0, 1.0, 1.0); 16];
+        let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates));
+        let mut tax = get_tax_mock(0.1);
+
+        for period in lifespan.iter() {
+            job.calculate_income_for_period(period, &mut tax);
+        }
+
+        // Deferrals hit the limit after 6 months, after which the contribution is taxed and then invested in the overflow account instead
+        assert_vecfloat_absolute(job.get_net_income().clone(), vec![450.0, 450.0, 450.0, 450.0, 450.0, 450.0, 400.0, 400.0, 400.0, 400.0, 400.0, 400.0, 450.0, 450.0, 450.0, 450.0]);
+        assert_vecfloat_absolute(job.account_contributions[0].account.balance().to_vec(), vec![500.0, 1000.0, 1500.0, 2000.0, 2500.0, 3000.0, 3000.0, 3000.0, 3000.0, 3000.0, 3000.0, 3000.0, 3500.0, 4000.0, 4500.0, 5000.0]);
+        assert_vecfloat_absolute(job.account_contributions[1].account.balance().to_vec(), vec![0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 500.0, 1000.0, 1500.0, 2000.0, 2500.0, 3000.0, 3000.0, 3000.0, 3000.0, 3000.0]);
+    }
+
+    #[test]
+    pub fn calculateincome_notax_annualadditionslimit_catchup_nooverflow() {
+        let asset_allocation = Rc::new(AssetAllocation::new(vec![0.5]));
+        let employee = AccountContributionSettings::new_with_limit(AccountSettings::new(0.0, Rc::clone(&asset_allocation)), 0.25, AccountContributionSource::Employee, AccountContributionTaxability::PreTax, AccountContributionLimit::EmployerPlan);
+        let employer = AccountContributionSettings::new_with_limit(AccountSettings::new(0.0, asset_allocation), 0.25, AccountContributionSource::Employer, AccountContributionTaxability::PreTax, AccountContributionLimit::EmployerPlan);
+        let limits = ContributionLimits::new(10000.0, 5000.0, 0.0, CatchUpLimits::new(50, 1000.0, 0.0), false);
+        let job_settings = JobSettings::new_with_limits(1000.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![employee, employer], limits, None);
+        let lifespan = Lifespan::new(24);
+        let rates = vec![Rate::new(1.0, 1.0, 1.0); 24];
+        // Turns 50 during the second year
+        let mut job = job_settings.create_job(lifespan, lifespan, 48 * 12 + 6, Rc::new(rates));
+        let mut tax = get_tax_mock(0.0);
+
+        for period in lifespan.iter() {
+            job.calculate_income_for_period(period, &mut tax);
+        }
+
+        let net = job.get_net_income();
+        // Combined 500/month hits the 5000 annual additions limit after 10 months, the employee's
+        // share of the excess is paid out since there's no overflow account
+        assert_vecfloat_absolute(net[..12].to_vec(), vec![750.0, 750.0, 750.0, 750.0, 750.0, 750.0, 750.0, 750.0, 750.0, 750.0, 1000.0, 1000.0]);
+        // Catch-up raises the limit to 6000 in the second year
+        assert_vecfloat_absolute(net[12..].to_vec(), vec![750.0, 750.0, 750.0, 750.0, 750.0, 750.0, 750.0, 750.0, 750.0, 750.0, 750.0, 750.0]);
+        assert_float_absolute_eq!(job.account_contributions[0].account.balance()[23], 2500.0 + 3000.0);
+        assert_float_absolute_eq!(job.account_contributions[1].account.balance()[23], 2500.0 + 3000.0);
+    }
+
     #[test]
     pub fn retire_fica_raise_10tax_employerpretax401k() {
         let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.5, 1, 0.5));
         let account = AccountSettings::new(0.0, asset_allocation);
-        let account_contributions = AccountContributionSettings { account, contribution_pct: 0.08, contribution_source: AccountContributionSource::Employer, tax: AccountContributionTaxability::PreTax };
-        let job_settings = JobSettings::new(1000.0, Fica::Participant { ss_rate: 0.0625 }, RaiseSettings {amount: 1.0625, adjust_for_inflation: true}, vec![account_contributions] );
+        let account_contributions = AccountContributionSettings { account, contribution_pct: 0.08, contribution_source: AccountContributionSource::Employer, tax: AccountContributionTaxability::PreTax, limit: AccountContributionLimit::Unlimited, employer_match: EmployerMatch::none(), vesting: Vesting::Immediate };
+        let job_settings = JobSettings::new(1000.0, flat_fica(0.0625), RaiseSettings {amount: 1.0625, adjust_for_inflation: true}, vec![account_contributions] );
         let lifespan = Lifespan::new(20);
         let careerspan = Lifespan::new(16);
         let rates = vec![Rate::new(1.006, 1.0, 1.002); 20];
-        let mut job = job_settings.create_job(lifespan, careerspan, Rc::new(rates));
+        let mut job = job_settings.create_job(lifespan, careerspan, 30 * 12, Rc::new(rates));
         let mut tax = get_tax_mock(0.1);
 
         for period in careerspan.iter() {
@@ -439,4 +1261,267 @@ mod tests {
         assert_eq!(accounts[0].balance().len(), 20);
     }
 
-}
\ No newline at end of file
+
+    fn safe_harbor_match(true_up: bool) -> EmployerMatch {
+        EmployerMatch::new(vec![MatchTier { floor: 0.0, rate: 1.0 }, MatchTier { floor: 0.03, rate: 0.5 }, MatchTier { floor: 0.05, rate: 0.0 }], true_up)
+    }
+
+    #[test]
+    pub fn employermatch_matchpct() {
+        let employer_match = safe_harbor_match(false);
+
+        assert_float_absolute_eq!(employer_match.match_pct(0.0), 0.0);
+        assert_float_absolute_eq!(employer_match.match_pct(0.02), 0.02);
+        assert_float_absolute_eq!(employer_match.match_pct(0.04), 0.035);
+        assert_float_absolute_eq!(employer_match.match_pct(0.10), 0.04);
+        assert_eq!(EmployerMatch::none().match_pct(0.10), 0.0);
+        assert_eq!(employer_match.match_for(0.0, 0.0), 0.0);
+    }
+
+    #[test]
+    pub fn vesting_vestedpct() {
+        assert_eq!(Vesting::Immediate.vested_pct(0), 1.0);
+
+        let cliff = Vesting::Cliff { years: 3 };
+        assert_eq!((cliff.vested_pct(0), cliff.vested_pct(2), cliff.vested_pct(3), cliff.vested_pct(10)), (0.0, 0.0, 1.0, 1.0));
+
+        let graded = Vesting::Graded { schedule: vec![0.0, 0.2, 0.4] };
+        assert_eq!((graded.vested_pct(0), graded.vested_pct(1), graded.vested_pct(2), graded.vested_pct(3)), (0.0, 0.2, 0.4, 1.0));
+    }
+
+    #[test]
+    pub fn calculateincome_employermatch_trueup() {
+        let asset_allocation = Rc::new(AssetAllocation::new(vec![0.5]));
+        let limits = ContributionLimits::new(3000.0, 100000.0, 0.0, CatchUpLimits::new(50, 0.0, 0.0), false);
+        let lifespan = Lifespan::new(13);
+        let rates = vec![Rate::new(1.0, 1.0, 1.0); 13];
+
+        let mut employer_balances = vec![];
+        for true_up in [false, true] {
+            let employee = AccountContributionSettings::new_with_limit(AccountSettings::new(0.0, Rc::clone(&asset_allocation)), 0.5, AccountContributionSource::Employee, AccountContributionTaxability::PreTax, AccountContributionLimit::EmployerPlan);
+            let employer = AccountContributionSettings::new_employer(AccountSettings::new(0.0, Rc::clone(&asset_allocation)), 0.01, safe_harbor_match(true_up), AccountContributionLimit::EmployerPlan, Vesting::Immediate);
+            let job_settings = JobSettings::new_with_limits(1000.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![employee, employer], limits, None);
+            let mut job = job_settings.create_job(lifespan, lifespan, 30 * 12, Rc::new(rates.clone()));
+            let mut tax = get_tax_mock(0.0);
+
+            for period in lifespan.iter() {
+                job.calculate_income_for_period(period, &mut tax);
+            }
+
+            employer_balances.push(job.account_contributions[1].account.balance().clone());
+        }
+
+        // Deferrals hit the limit after 6 months, so there's no match for the rest of the year
+        // without a true-up.  Nonelective contributions of 10/month continue regardless.
+        assert_float_absolute_eq!(employer_balances[0][5], 6.0 * 50.0);
+        assert_float_absolute_eq!(employer_balances[0][11], 6.0 * 40.0 + 12.0 * 10.0);
+        // Deferring 25% of the year's gross earns the full 4% match on 12000 at the end of the year
+        assert_float_absolute_eq!(employer_balances[1][10], 6.0 * 40.0 + 11.0 * 10.0);
+        assert_float_absolute_eq!(employer_balances[1][11], 480.0 + 12.0 * 10.0);
+        assert_float_absolute_eq!(employer_balances[1][12], 480.0 + 13.0 * 10.0 + 40.0);
+    }
+
+    #[test]
+    pub fn calculateincome_employermatch_trueupwhenleaving() {
+        let asset_allocation = Rc::new(AssetAllocation::new(vec![0.5]));
+        let limits = ContributionLimits::new(3000.0, 100000.0, 0.0, CatchUpLimits::new(50, 0.0, 0.0), false);
+        let lifespan = Lifespan::new(13);
+        let rates = vec![Rate::new(1.0, 1.0, 1.0); 13];
+
+        let employee = AccountContributionSettings::new_with_limit(AccountSettings::new(0.0, Rc::clone(&asset_allocation)), 0.5, AccountContributionSource::Employee, AccountContributionTaxability::PreTax, AccountContributionLimit::EmployerPlan);
+        let employer = AccountContributionSettings::new_employer(AccountSettings::new(0.0, Rc::clone(&asset_allocation)), 0.01, safe_harbor_match(true), AccountContributionLimit::EmployerPlan, Vesting::Immediate);
+        let job_settings = JobSettings::new_with_limits(1000.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![employee, employer], limits, None);
+        let mut job = job_settings.create_job_between(lifespan, lifespan, 0, 9, 30 * 12, Rc::new(rates));
+        let mut tax = get_tax_mock(0.0);
+
+        for period in lifespan.iter().take(9) {
+            job.calculate_income_for_period(period, &mut tax);
+        }
+
+        // Leaving after 9 months trues up to the full 4% match on 9000 in the last month
+        let employer_balance = job.account_contributions[1].account.balance();
+        assert_float_absolute_eq!(employer_balance[7], 6.0 * 40.0 + 8.0 * 10.0);
+        assert_float_absolute_eq!(employer_balance[8], 360.0 + 9.0 * 10.0);
+    }
+
+    #[test]
+    #[should_panic(expected = "an employer match needs an employee account with an EmployerPlan limit to match")]
+    pub fn jobsettings_matchwithoutplandeferrals() {
+        let asset_allocation = Rc::new(AssetAllocation::new(vec![0.5]));
+        let employee = AccountContributionSettings::new(AccountSettings::new(0.0, Rc::clone(&asset_allocation)), 0.05, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
+        let employer = AccountContributionSettings::new_employer(AccountSettings::new(0.0, asset_allocation), 0.0, safe_harbor_match(false), AccountContributionLimit::EmployerPlan, Vesting::Immediate);
+
+        JobSettings::new(1000.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![employee, employer]);
+    }
+
+    #[test]
+    pub fn retire_gradedvesting_fullyvests() {
+        let asset_allocation = Rc::new(AssetAllocation::new(vec![0.5]));
+        let employer = AccountContributionSettings::new_employer(AccountSettings::new(0.0, asset_allocation), 0.1, EmployerMatch::none(), AccountContributionLimit::Unlimited, Vesting::Graded { schedule: vec![0.0, 0.5] });
+        let job_settings = JobSettings::new(1000.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![employer]);
+        let lifespan = Lifespan::new(20);
+        let careerspan = Lifespan::new(18);
+        let rates = vec![Rate::new(1.0, 1.0, 1.0); 20];
+        let mut job = job_settings.create_job(lifespan, careerspan, 30 * 12, Rc::new(rates));
+        let mut tax = get_tax_mock(0.0);
+
+        for period in careerspan.iter() {
+            job.calculate_income_for_period(period, &mut tax);
+        }
+
+        let account = &job.account_contributions[0].account;
+        assert_float_absolute_eq!(account.vested_balance()[10], 0.0);
+        assert_float_absolute_eq!(account.unvested_balance()[10], 1100.0);
+        assert_float_absolute_eq!(account.vested_balance()[11], 600.0);
+        assert_float_absolute_eq!(account.vested_balance()[17], 900.0);
+
+        // Nothing is forfeited at retirement, even after only 1 year and 6 months of service
+        let (_, accounts) = job.retire();
+        assert_float_absolute_eq!(accounts[0].balance()[17], 1800.0);
+        assert_float_absolute_eq!(accounts[0].unvested_balance()[17], 0.0);
+    }
+
+    #[test]
+    pub fn career_cliffvesting_forfeitsonleaving() {
+        let asset_allocation = Rc::new(AssetAllocation::new(vec![0.5]));
+        let employer = AccountContributionSettings::new_employer(AccountSettings::new(1000.0, asset_allocation), 0.1, EmployerMatch::none(), AccountContributionLimit::Unlimited, Vesting::Cliff { years: 3 });
+        let job_settings = JobSettings::new(1000.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![employer]);
+        let mut career_settings = CareerSettings::new();
+        career_settings.add(CareerJobSettings::new(job_settings, 30 * 12, Some(30 * 12 + 18)));
+
+        let lifespan = Lifespan::new(24);
+        let rates = vec![Rate::new(1.0, 1.0, 1.0); 24];
+        let mut career = career_settings.create_career(lifespan, lifespan, 30 * 12, Rc::new(rates));
+        let mut tax = get_tax_mock(0.0);
+        for period in lifespan.iter() {
+            career.calculate_income_for_period(period, &mut tax);
+        }
+
+        // Only the 100 a month contributed by the employer is unvested, not the balance the
+        // account started with, and it's forfeited when the job ends before retirement
+        let (_, accounts) = career.retire();
+        assert_float_absolute_eq!(accounts[0].balance()[16], 1000.0 + 17.0 * 100.0);
+        assert_float_absolute_eq!(accounts[0].unvested_balance()[16], 17.0 * 100.0);
+        assert_float_absolute_eq!(accounts[0].balance()[17], 1000.0);
+        assert_float_absolute_eq!(accounts[0].balance()[23], 1000.0);
+    }
+
+    #[test]
+    pub fn career_sequentialconcurrentjobs_rollover() {
+        let asset_allocation = Rc::new(AssetAllocation::new(vec![0.5]));
+        let deferred = || AccountSettings::new_with_tax_treatment(0.0, Rc::clone(&asset_allocation), Rc::new(FeeSettings::none()), AccountTaxTreatment::TaxDeferred);
+        let no_raise = RaiseSettings {amount: 1.0, adjust_for_inflation: false};
+
+        // Six months at the first job, a three month gap, then the second job until retirement with
+        // six months of a side job on top
+        let first = JobSettings::new(1000.0, Fica::Exempt, no_raise, vec![
+            AccountContributionSettings::new(deferred(), 0.1, AccountContributionSource::Employee, AccountContributionTaxability::PreTax),
+            AccountContributionSettings::new(AccountSettings::new(0.0, Rc::clone(&asset_allocation)), 0.05, AccountContributionSource::Employee, AccountContributionTaxability::PostTax)
+        ]);
+        let second = JobSettings::new(2000.0, flat_fica(0.0), no_raise, vec![
+            AccountContributionSettings::new(deferred(), 0.1, AccountContributionSource::Employee, AccountContributionTaxability::PreTax)
+        ]);
+        let side = JobSettings::new(500.0, flat_fica(0.0), no_raise, vec![]);
+
+        let mut career_settings = CareerSettings::new();
+        career_settings.add(CareerJobSettings::new(first, 30 * 12, Some(30 * 12 + 6)).with_rollover(AccountSettings::new(0.0, Rc::clone(&asset_allocation))));
+        career_settings.add(CareerJobSettings::new(second, 30 * 12 + 9, None));
+        career_settings.add(CareerJobSettings::new(side, 31 * 12, Some(31 * 12 + 6)));
+
+        let lifespan = Lifespan::new(26);
+        let careerspan = Lifespan::new(24);
+        let rates = vec![Rate::new(1.0, 1.0, 1.0); 26];
+        let mut career = career_settings.create_career(lifespan, careerspan, 30 * 12, Rc::new(rates));
+        let mut tax = get_tax_mock(0.0);
+
+        for period in careerspan.iter() {
+            career.calculate_income_for_period(period, &mut tax);
+        }
+
+        let net_income = career.get_net_income();
+        assert_eq!((net_income[0], net_income[7], net_income[9], net_income[12], net_income[18]), (850.0, 0.0, 1800.0, 2300.0, 1800.0));
+        assert_eq!((career.covered_earnings()[5], career.covered_earnings()[12]), (0.0, 2500.0));
+
+        // The old 401k is rolled into an IRA, but the taxable account stays and keeps growing
+        let (monthly_net_salary, accounts) = career.retire();
+        assert_float_absolute_eq!(monthly_net_salary, (12.0 * 1800.0 + 6.0 * 500.0) / 12.0);
+        assert_eq!(accounts.len(), 3);
+        assert_eq!(accounts[0].tax_treatment(), AccountTaxTreatment::TaxDeferred);
+        assert_float_absolute_eq!(accounts[0].balance()[5], 600.0);
+        assert_float_absolute_eq!(accounts[0].balance()[23], 600.0);
+        assert_float_absolute_eq!(accounts[1].balance()[23], 300.0);
+        assert_float_absolute_eq!(accounts[2].balance()[23], 15.0 * 200.0);
+    }
+
+    #[test]
+    pub fn career_futurejob_startingbalance() {
+        let asset_allocation = Rc::new(AssetAllocation::new(vec![1.0]));
+        let job = JobSettings::new(1000.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![
+            AccountContributionSettings::new(AccountSettings::new(1000.0, asset_allocation), 0.0, AccountContributionSource::Employee, AccountContributionTaxability::PostTax)
+        ]);
+        let mut career_settings = CareerSettings::new();
+        career_settings.add(CareerJobSettings::new(job, 30 * 12 + 3, None));
+
+        // Stocks double every month
+        let lifespan = Lifespan::new(6);
+        let mut career = career_settings.create_career(lifespan, lifespan, 30 * 12, Rc::new(vec![Rate::new(2.0, 1.0, 1.0); 6]));
+        let mut tax = get_tax_mock(0.0);
+        for period in lifespan.iter() {
+            career.calculate_income_for_period(period, &mut tax);
+        }
+
+        // The starting balance only starts growing once the job does
+        let (_, accounts) = career.retire();
+        assert_eq!(accounts[0].balance(), &vec![1000.0, 1000.0, 1000.0, 2000.0, 4000.0, 8000.0]);
+    }
+
+    #[test]
+    pub fn career_rollover_keepsfees() {
+        let asset_allocation = Rc::new(AssetAllocation::new(vec![0.5]));
+        let fees = Rc::new(FeeSettings::new(0.12, 0.0, 0.0, vec![]));
+        let plan = AccountSettings::new_with_tax_treatment(12000.0, Rc::clone(&asset_allocation), fees, AccountTaxTreatment::TaxDeferred);
+        let job = JobSettings::new(1000.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![
+            AccountContributionSettings::new(plan, 0.0, AccountContributionSource::Employee, AccountContributionTaxability::PreTax)
+        ]);
+        let lifespan = Lifespan::new(12);
+        let rates = vec![Rate::new(1.0, 1.0, 1.0); 12];
+
+        let mut fees_paid = vec![];
+        for rollover in [None, Some(AccountSettings::new(0.0, Rc::clone(&asset_allocation)))] {
+            let mut career_settings = CareerSettings::new();
+            let settings = CareerJobSettings::new(job.clone(), 30 * 12, Some(30 * 12 + 6));
+            career_settings.add(match rollover {
+                Some(rollover) => settings.with_rollover(rollover),
+                None => settings
+            });
+
+            let mut career = career_settings.create_career(lifespan, lifespan, 30 * 12, Rc::new(rates.clone()));
+            let mut tax = get_tax_mock(0.0);
+            for period in lifespan.iter() {
+                career.calculate_income_for_period(period, &mut tax);
+            }
+
+            let (_, accounts) = career.retire();
+            assert_eq!(accounts.len(), 1);
+            fees_paid.push(accounts[0].fees_paid().clone());
+        }
+
+        // The fees paid before the rollover stay with the account, and the IRA has no fees
+        assert!(fees_paid[1][0] > 0.0);
+        assert_eq!(fees_paid[1][..6], fees_paid[0][..6]);
+        assert_eq!(fees_paid[1][6..], [0.0; 6]);
+    }
+
+    #[test]
+    pub fn createaccountcontribution_defaulttaxtreatment() {
+        let asset_allocation = Rc::new(AssetAllocation::new(vec![0.5]));
+        let account = AccountSettings::new(0.0, Rc::clone(&asset_allocation));
+        let roth = AccountSettings::new_with_tax_treatment(0.0, asset_allocation, Rc::new(FeeSettings::none()), AccountTaxTreatment::TaxFree);
+        let create = |account: &AccountSettings, tax| AccountContributionSettings::new(account.clone(), 0.1, AccountContributionSource::Employee, tax)
+            .create_account_contribution(Lifespan::new(1), Rc::new(vec![Rate::new(1.0, 1.0, 1.0)])).account.tax_treatment();
+
+        assert_eq!(create(&account, AccountContributionTaxability::PreTax), AccountTaxTreatment::TaxDeferred);
+        assert_eq!(create(&account, AccountContributionTaxability::PostTax), AccountTaxTreatment::Taxable);
+        assert_eq!(create(&roth, AccountContributionTaxability::PostTax), AccountTaxTreatment::TaxFree);
+    }
+}
@@ -8,3 +8,6 @@ mod person;
 mod util;
 mod income;
 mod taxes;
+mod spending;
+mod socialsecurity;
+mod pension;
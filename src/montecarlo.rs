@@ -1,17 +1,20 @@
-use std::cell::Ref;
 use std::rc::Rc;
 
 use rand::prelude::*;
 use wasm_bindgen::prelude::*;
 use js_sys::Float64Array;
 
-use crate::income::{JobSettings, IncomeProvider};
-use crate::person::PersonSettings;
-use crate::rates::{Rate, RatesSource, RatesSourceHolder};
-use crate::assets::{Account};
-use crate::taxes::{TaxSettings, TaxCollector, Tax};
+use crate::income::{CareerSettings, JobSettings, IncomeProvider};
+use crate::pension::Pension;
+use crate::person::{Person, PersonSettings};
+use crate::rates::{Rate, RatesSourceHolder, cumulative_inflation};
+use crate::assets::{Account, AccountSettings, AccountTaxTreatment, AllocationContext, AssetAllocation, FeeSettings};
+use crate::simplifying_assumption;
+use crate::taxes::{TaxSettings, TaxCollector, Tax, Money};
+use crate::socialsecurity::SocialSecurity;
+use crate::spending::{ScheduledExpense, SpendingPlan, SpendingPriority};
 use crate::util::Ratio;
-use crate::withdrawal::{WithdrawalStrategyOrig,WithdrawalStrategy};
+use crate::withdrawal::{WithdrawalRule, WithdrawalSettings, WithdrawalSettingsJS, WithdrawalContext, RequiredMinimumDistributions, RothConversions, SHORTFALL_TOLERANCE, collect_withdrawal_taxes};
 
 #[derive(Copy, Clone, Debug, PartialEq, PartialOrd, Eq, Ord)]
 #[wasm_bindgen]
@@ -110,68 +113,412 @@ pub struct Run {
     assets_adequate_periods: usize,
     lifespan: Lifespan,
     careerspan: Lifespan,
-    retirement_accounts: Vec<Account>
+    person: Person,
+    retirement_accounts: Vec<Account>,
+    lifetime_taxes: f64,
+    // Total benefits paid, in today's dollars
+    social_security_benefits: f64,
+    // Monthly pension left to a survivor at death
+    survivor_pension: f64,
+    // The annuities as they started, leaving out any taken as a lump sum
+    pensions_at_retirement: Vec<Pension>,
+    // Spending that couldn't be covered in each period, scheduled expenses included
+    shortfall: Vec<f64>,
+    // The part of the shortfall that was essential spending, after cutting discretionary spending
+    essential_shortfall: Vec<f64>,
+    // Whether each scheduled expense was always paid in full, or None if it never came due
+    expenses_funded: Vec<Option<bool>>
 }
 
 impl Run {
-    pub fn execute<T: SeedableRng + Rng + Clone + std::fmt::Debug, U: TaxCollector + std::fmt::Debug>(seed: u64, rates_source: Ref<RatesSource>, sublength: usize, job_settings: &JobSettings, person_settings: &PersonSettings, career_periods: usize, tax_settings: TaxSettings) -> Run {
+    // The career and withdrawal settings are passed separately so callers can vary them while
+    // keeping the rest of the settings
+    pub fn execute<T: SeedableRng + Rng + Clone + std::fmt::Debug, U: TaxCollector + std::fmt::Debug>(seed: u64, settings: &SimulationSettings, career_settings: &CareerSettings, withdrawal_settings: &WithdrawalSettings) -> Run {
         let mut rng = T::seed_from_u64(seed);
 
-        let person = person_settings.create_person(&mut rng);
+        let person = settings.person_settings.create_person(&mut rng);
         let lifespan = person.lifespan();
-        let careerspan = Lifespan::new(career_periods);
-        let rates = Rc::new(rates_source.generate_rates(T::seed_from_u64(rng.gen()), sublength, lifespan.periods()));
-        let jobs = job_settings.create_job(lifespan, careerspan, Rc::clone(&rates));
-        let tax = U::new(tax_settings, Rc::clone(&rates), lifespan);
+        let careerspan = Lifespan::new(settings.career_periods);
+        let rates = Rc::new(settings.rates_source.get_rates_source().generate_rates(T::seed_from_u64(rng.gen()), settings.sublength, lifespan.periods()));
+        let career = career_settings.create_career(lifespan, careerspan, person.age_months(Period { period: 0 }), Rc::clone(&rates));
+        // TODO figure out a way to avoid cloning tax_settings here
+        let tax = U::new(settings.tax_settings.clone(), Rc::clone(&rates), lifespan);
 
         let mut run = Run {
             rates,
             assets_adequate_periods: 0,
             lifespan,
             careerspan,
-            retirement_accounts: Vec::with_capacity(jobs.account_contributions().len())
+            person,
+            retirement_accounts: vec![],
+            lifetime_taxes: 0.0,
+            social_security_benefits: 0.0,
+            survivor_pension: 0.0,
+            pensions_at_retirement: vec![],
+            shortfall: vec![0.0; lifespan.periods()],
+            essential_shortfall: vec![0.0; lifespan.periods()],
+            expenses_funded: vec![]
         };
 
-        run.populate(jobs, tax);
+        run.populate(career, tax, withdrawal_settings);
 
         run
     }
 
-    fn populate<T: IncomeProvider, U: TaxCollector>(&mut self, mut job: T, mut tax: U) {
+    fn populate<T: IncomeProvider, U: TaxCollector>(&mut self, mut job: T, mut tax: U, withdrawal_settings: &WithdrawalSettings) {
         let mut life_iter = self.lifespan.iter();
+        let expenses: Vec<ScheduledExpense> = withdrawal_settings.spending_plan.as_ref().map_or(vec![], |plan| plan.expenses().to_vec());
+        self.expenses_funded = vec![None; expenses.len()];
+        // Stays set once savings or the portfolio first fall short
+        let mut depleted = false;
 
         // Run until either we hit retirement or we die
         while let Some(period) = life_iter.next() {
             job.calculate_income_for_period(period, &mut tax);
 
-            self.assets_adequate_periods += 1;
-            
+            for (i, expense) in expenses.iter().enumerate() {
+                let amount = expense.amount_due(self.person.age_months(period), &self.rates, period);
+                if amount > 0.0 {
+                    let unfunded = job.pay_expense(amount, period);
+                    self.record_expense(i, unfunded);
+                    self.shortfall[period.get()] += unfunded;
+                    if expense.priority() == SpendingPriority::Essential {
+                        self.essential_shortfall[period.get()] += unfunded;
+                    }
+                }
+            }
+
+            depleted |= self.shortfall[period.get()] >= SHORTFALL_TOLERANCE;
+            if !depleted {
+                self.assets_adequate_periods += 1;
+            }
+
             // Check if we've hit retirement
             if period.get() == self.careerspan.periods() - 1 {
                 break;
             }
         }
 
+        let social_security = withdrawal_settings.social_security.as_ref()
+            .map(|settings| SocialSecurity::new(settings, job.covered_earnings(), self.person.age_months(Period { period: 0 }), &self.rates));
+        let mut pensions = job.pensions();
         let (pre_retirement_monthly_income, mut retirement_accounts) = job.retire();
-        // TODO make WithdrawalStrategy smart enough to know about taxes
-        let withdrawal_strategy = WithdrawalStrategyOrig::new();
+        let retirement = Period { period: self.careerspan.periods() };
+        let mut withdrawal_strategy = withdrawal_settings.create_strategy(pre_retirement_monthly_income);
+        // Dying before retirement leaves no retirement periods to spend in
+        let inflation_at_retirement = cumulative_inflation(&self.rates, Period { period: usize::min(retirement.get(), self.rates.len()) });
+        let spending_plan = withdrawal_settings.spending_plan.clone()
+            .unwrap_or_else(|| SpendingPlan::flat(pre_retirement_monthly_income * 12.0 / inflation_at_retirement));
+
+        let mut rmds = withdrawal_settings.rmd_start_age.map(RequiredMinimumDistributions::new);
+        let roth_conversions = withdrawal_settings.roth_conversions.map(RothConversions::new);
+        let needs_account = |accounts: &[Account], tax_treatment| !accounts.is_empty() && accounts.iter().all(|a| a.tax_treatment() != tax_treatment);
+        if rmds.is_some() && needs_account(&retirement_accounts, AccountTaxTreatment::Taxable) {
+            let reinvestment_account = retirement_accounts[0].create_empty_account(AccountTaxTreatment::Taxable);
+            retirement_accounts.push(reinvestment_account);
+        }
+        if roth_conversions.is_some() && needs_account(&retirement_accounts, AccountTaxTreatment::TaxFree) {
+            let roth_account = retirement_accounts[0].create_empty_account(AccountTaxTreatment::TaxFree);
+            retirement_accounts.push(roth_account);
+        }
 
-        // TODO change withdrawal amount from pre_retirement_income
+        // A pension lump sum is rolled over into its own tax-deferred account at the end of the
+        // career, instead of paying the annuity
+        simplifying_assumption!("Pension lump sums are invested 60/40 without fees if there are no \
+            other accounts to copy an allocation from.");
+        if !self.lifespan.contains(retirement) {
+            pensions.clear();
+        }
+        for lump_sum in pensions.iter().filter_map(|pension| pension.lump_sum_taken()) {
+            let mut rollover_account = match retirement_accounts.first() {
+                Some(account) => account.create_empty_account(AccountTaxTreatment::TaxDeferred),
+                None => AccountSettings::new_with_tax_treatment(0.0, Rc::new(AssetAllocation::new(vec![0.6])), Rc::new(FeeSettings::none()), AccountTaxTreatment::TaxDeferred)
+                    .create_account(self.lifespan, Rc::clone(&self.rates))
+            };
+            rollover_account.deposit(lump_sum, retirement - 1);
+            retirement_accounts.push(rollover_account);
+        }
+        pensions.retain(|pension| pension.lump_sum_taken().is_none());
+        self.pensions_at_retirement = pensions.clone();
+
+        let mut portfolio_history: Vec<f64> = (0..usize::min(retirement.get(), self.lifespan.periods()))
+            .map(|period| retirement_accounts.iter().map(|a| a.balance_before(Period { period })).sum())
+            .collect();
+        let mut past_withdrawals = vec![];
 
         for period in life_iter {
+            portfolio_history.push(retirement_accounts.iter().map(|a| a.balance_before(period)).sum());
+
+            simplifying_assumption!("A flat 85% of Social Security benefits is taxable, without the \
+                provisional income test, and benefits beyond what's being spent aren't saved.");
+            let benefit = social_security.as_ref().map_or(0.0, |ss| ss.monthly_benefit(period, self.person.age_months(period), &self.rates));
+            self.social_security_benefits += benefit / cumulative_inflation(&self.rates, period);
+            let benefit = if benefit > 0.0 {
+                tax.collect_income_taxes(Money::Taxable(benefit * 0.85), period).leftover() + benefit * 0.15
+            } else {
+                0.0
+            };
+
+            // Pensions are fully taxable
+            let pension_payment: f64 = pensions.iter_mut().map(|pension| pension.payment(period, &self.rates)).sum();
+            let pension_payment = if pension_payment > 0.0 {
+                tax.collect_income_taxes(Money::Taxable(pension_payment), period).leftover()
+            } else {
+                0.0
+            };
+            let guaranteed_income = benefit + pension_payment;
+
+            // Only the spending the portfolio has to fund counts against it
+            let monthly_spending = spending_plan.monthly_spending(self.person.age_months(period), cumulative_inflation(&self.rates, period));
+            let context = AllocationContext {
+                portfolio: portfolio_history[period.get()],
+                monthly_spending: f64::max(monthly_spending.total() - guaranteed_income, 0.0),
+                life_expectancy_years: self.person.remaining_life_expectancy(period)
+            };
+
             for account in &mut retirement_accounts {
-                account.rebalance_and_invest_next_period(period);
+                account.rebalance_and_invest_next_period_with_context(period, Some(&context));
             }
 
-            match withdrawal_strategy.execute(pre_retirement_monthly_income, &mut retirement_accounts, period) {
-                Ok(_) => {},
-                Err(_) => { break; }
+            let context = WithdrawalContext {
+                period,
+                retirement,
+                age_months: self.person.age_months(period),
+                remaining_life_expectancy: self.person.remaining_life_expectancy(period),
+                rates: &self.rates,
+                portfolio_history: &portfolio_history,
+                past_withdrawals: &past_withdrawals,
+                tax: &tax,
+                spending_plan: &spending_plan,
+                guaranteed_income,
+                order: None
+            };
+
+            // Scheduled expenses are withdrawn along with regular spending so they're grossed up for
+            // taxes together
+            let due: Vec<(usize, f64)> = expenses.iter().enumerate()
+                .map(|(i, expense)| (i, expense.amount_due(context.age_months, &self.rates, period)))
+                .filter(|(_, amount)| *amount > 0.0)
+                .collect();
+            let spending = withdrawal_strategy.withdrawal_amount(&retirement_accounts, &context);
+            let essential = context.planned_spending().essential;
+            let needed = spending + due.iter().map(|(_, amount)| amount).sum::<f64>();
+            let result = withdrawal_strategy.withdraw(f64::max(needed - guaranteed_income, 0.0), &mut retirement_accounts, &context);
+            collect_withdrawal_taxes(&result, &retirement_accounts, &mut tax, period);
+
+            // Whatever couldn't be covered comes out of discretionary expenses first, then essential
+            // ones, then regular spending
+            let mut unfunded = result.shortfall;
+            let mut essential_unfunded = 0.0;
+            for priority in [SpendingPriority::Discretionary, SpendingPriority::Essential] {
+                for &(i, amount) in due.iter().filter(|(i, _)| expenses[*i].priority() == priority) {
+                    let expense_unfunded = f64::min(unfunded, amount);
+                    unfunded -= expense_unfunded;
+                    self.record_expense(i, expense_unfunded);
+                    if priority == SpendingPriority::Essential {
+                        essential_unfunded += expense_unfunded;
+                    }
+                }
             }
 
-            self.assets_adequate_periods += 1;
+            // Keep going after the portfolio runs out, living on whatever is left
+            self.shortfall[period.get()] = result.shortfall;
+
+            // Anything the strategy withdrew beyond essential spending gets cut first
+            let discretionary = f64::max(spending - essential, 0.0);
+            self.essential_shortfall[period.get()] = essential_unfunded + f64::max(unfunded - discretionary, 0.0);
+            if let Some(rmds) = &mut rmds {
+                rmds.distribute(&result, &mut retirement_accounts, &mut tax, period, self.person.age_months(period));
+            }
+            if let Some(roth_conversions) = &roth_conversions {
+                roth_conversions.convert(&mut retirement_accounts, &mut tax, &self.rates, period, self.person.age_months(period));
+            }
+            past_withdrawals.push(result.total());
+
+            depleted |= self.shortfall[period.get()] >= SHORTFALL_TOLERANCE;
+            if !depleted {
+                self.assets_adequate_periods += 1;
+            }
         }
 
         self.retirement_accounts = retirement_accounts;
+        self.lifetime_taxes = tax.total_taxes();
+        self.survivor_pension = pensions.iter().map(|pension| pension.survivor_benefit()).sum();
+    }
+
+    fn record_expense(&mut self, expense: usize, unfunded: f64) {
+        let funded = self.expenses_funded[expense].unwrap_or(true) && unfunded < SHORTFALL_TOLERANCE;
+        self.expenses_funded[expense] = Some(funded);
+    }
+
+    // The first period spending couldn't be fully covered
+    fn depletion_period(&self) -> Option<Period> {
+        self.shortfall.iter().position(|s| *s >= SHORTFALL_TOLERANCE).map(|period| Period { period })
+    }
+
+    // In today's dollars
+    fn total_shortfall(&self) -> f64 {
+        self.shortfall.iter().enumerate()
+            .map(|(period, shortfall)| shortfall / cumulative_inflation(&self.rates, Period { period }))
+            .sum()
+    }
+
+    fn years_in_shortfall(&self) -> f64 {
+        self.shortfall.iter().filter(|s| **s >= SHORTFALL_TOLERANCE).count() as f64 / 12.0
+    }
+
+    // What's left in the accounts at death, in today's dollars
+    fn terminal_wealth(&self) -> f64 {
+        let balance: f64 = self.retirement_accounts.iter().filter_map(|a| a.balance().last()).sum();
+        balance / cumulative_inflation(&self.rates, Period { period: self.rates.len() })
+    }
+}
+
+fn median(mut values: Vec<f64>) -> f64 {
+    if values.is_empty() {
+        return 0.0;
+    }
+
+    values.sort_by(|a, b| a.total_cmp(b));
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) { (values[mid - 1] + values[mid]) / 2.0 } else { values[mid] }
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+#[wasm_bindgen]
+pub enum ClaimingAgeObjective {
+    SuccessRate,
+    LifetimeBenefits,
+    TerminalWealth
+}
+
+// Outcomes of a simulation with Social Security claimed at one age, in today's dollars
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct ClaimingAgeResult {
+    pub claiming_age_months: usize,
+    pub success_rate: Ratio,
+    pub mean_lifetime_benefits: f64,
+    pub median_terminal_wealth: f64
+}
+
+impl ClaimingAgeResult {
+    fn new(claiming_age_months: usize, simulation: &Simulation) -> ClaimingAgeResult {
+        ClaimingAgeResult {
+            claiming_age_months,
+            success_rate: simulation.success_rate(),
+            mean_lifetime_benefits: simulation.runs.iter().map(|r| r.social_security_benefits).sum::<f64>() / simulation.runs.len() as f64,
+            median_terminal_wealth: median(simulation.runs.iter().map(|r| r.terminal_wealth()).collect())
+        }
+    }
+
+    fn score(&self, objective: ClaimingAgeObjective) -> f64 {
+        match objective {
+            ClaimingAgeObjective::SuccessRate => self.success_rate.num as f64 / self.success_rate.denom as f64,
+            ClaimingAgeObjective::LifetimeBenefits => self.mean_lifetime_benefits,
+            ClaimingAgeObjective::TerminalWealth => self.median_terminal_wealth
+        }
+    }
+}
+
+#[wasm_bindgen]
+pub struct ClaimingAgeTable {
+    results: Vec<ClaimingAgeResult>
+}
+
+#[wasm_bindgen]
+impl ClaimingAgeTable {
+    #[wasm_bindgen]
+    pub fn count(&self) -> usize {
+        self.results.len()
+    }
+
+    #[wasm_bindgen]
+    pub fn get(&self, index: usize) -> ClaimingAgeResult {
+        self.results[index]
+    }
+
+    // The earliest claiming age with the best score
+    #[wasm_bindgen]
+    pub fn best(&self, objective: ClaimingAgeObjective) -> ClaimingAgeResult {
+        *self.results.iter().rev().max_by(|a, b| a.score(objective).total_cmp(&b.score(objective))).unwrap()
+    }
+}
+
+// Taking pensions as annuities or as lump sums, simulated over the same market returns and
+// lifespans
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct PensionComparison {
+    pub annuity_success_rate: Ratio,
+    pub lump_sum_success_rate: Ratio,
+    // Average present value of the annuity at retirement, among runs that lived to retire
+    pub mean_annuity_value: f64,
+    breakeven_age_months: Option<usize>
+}
+
+#[wasm_bindgen]
+impl PensionComparison {
+    // Median age when the annuity's discounted payments add up to the lump sum, or None if it
+    // usually never does
+    #[wasm_bindgen]
+    pub fn breakeven_age_months(&self) -> Option<usize> {
+        self.breakeven_age_months
+    }
+}
+
+// Everything a simulation needs, with one job for the whole career and the original withdrawal
+// rule unless they're replaced
+#[wasm_bindgen]
+pub struct SimulationSettings {
+    seed: u64,
+    count: usize,
+    rates_source: RatesSourceHolder,
+    sublength: usize,
+    career_settings: CareerSettings,
+    person_settings: PersonSettings,
+    career_periods: usize,
+    tax_settings: TaxSettings,
+    withdrawal_settings: WithdrawalSettings
+}
+
+#[wasm_bindgen]
+impl SimulationSettings {
+    #[wasm_bindgen(constructor)]
+    pub fn new(seed: u64, count: usize, rates_source: RatesSourceHolder, sublength: usize, person_settings: PersonSettings, career_periods: usize, tax_settings: TaxSettings) -> SimulationSettings {
+        SimulationSettings {
+            seed,
+            count,
+            rates_source,
+            sublength,
+            career_settings: CareerSettings::default(),
+            person_settings,
+            career_periods,
+            tax_settings,
+            withdrawal_settings: WithdrawalRule::Orig.into()
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn with_job(self, job_settings: JobSettings) -> SimulationSettings {
+        SimulationSettings { career_settings: job_settings.into(), ..self }
+    }
+
+    // A career of several jobs instead of one job for the whole career
+    #[wasm_bindgen]
+    pub fn with_career(self, career_settings: CareerSettings) -> SimulationSettings {
+        SimulationSettings { career_settings, ..self }
+    }
+
+    #[wasm_bindgen]
+    pub fn with_withdrawal(self, withdrawal_settings: WithdrawalSettingsJS) -> SimulationSettings {
+        self.with_withdrawal_settings(withdrawal_settings.withdrawal_settings())
+    }
+}
+
+impl SimulationSettings {
+    pub fn with_withdrawal_settings(self, withdrawal_settings: WithdrawalSettings) -> SimulationSettings {
+        SimulationSettings { withdrawal_settings, ..self }
     }
 }
 
@@ -183,8 +530,23 @@ pub struct Simulation {
 #[wasm_bindgen]
 impl Simulation {
     #[wasm_bindgen(constructor)]
-    pub fn new_default(seed: u64, count: usize, rates_source: RatesSourceHolder, sublength: usize, job_settings: JobSettings, person_settings: PersonSettings, career_periods: usize, tax_settings: TaxSettings) -> Simulation {
-        Self::new::<rand_pcg::Pcg64Mcg, Tax>(seed, count, rates_source, sublength, job_settings, person_settings, career_periods, tax_settings)
+    pub fn new_default(settings: &SimulationSettings) -> Simulation {
+        Self::new::<rand_pcg::Pcg64Mcg, Tax>(settings)
+    }
+
+    // Re-runs the simulation claiming Social Security at each whole age from 62 to 70.  Every
+    // claiming age uses the same seed, so they all see the same market returns and lifespans.
+    // None if there are no Social Security settings.
+    #[wasm_bindgen]
+    pub fn claiming_age_table(settings: &SimulationSettings) -> Option<ClaimingAgeTable> {
+        Self::claiming_ages::<rand_pcg::Pcg64Mcg, Tax>(settings)
+    }
+
+    // Compares the career's pension lump sums to their annuities, with the annuities priced at the
+    // annual discount rate.  None if no job has a pension with a lump sum offer.
+    #[wasm_bindgen]
+    pub fn pension_comparison(settings: &SimulationSettings, discount_rate: f64) -> Option<PensionComparison> {
+        Self::compare_pension::<rand_pcg::Pcg64Mcg, Tax>(settings, discount_rate)
     }
 
     #[wasm_bindgen]
@@ -195,6 +557,15 @@ impl Simulation {
         }
     }
 
+    // Runs that could always cover essential spending, even if discretionary spending was cut
+    #[wasm_bindgen]
+    pub fn essential_success_rate(&self) -> Ratio {
+        Ratio {
+            num: self.runs.iter().filter(|a| a.essential_shortfall.iter().all(|s| *s < SHORTFALL_TOLERANCE)).count(),
+            denom: self.runs.len()
+        }
+    }
+
     #[wasm_bindgen]
     pub fn assets_adequate_periods_for_run(&self, run: usize) -> usize {
        self.runs[run].assets_adequate_periods
@@ -205,6 +576,78 @@ impl Simulation {
        self.runs[run].lifespan
     }
 
+    #[wasm_bindgen]
+    pub fn fees_paid_for_run(&self, run: usize) -> f64 {
+       self.runs[run].retirement_accounts.iter().map(|a| a.total_fees_paid()).sum()
+    }
+
+    // Age in months when the portfolio ran out, if it did
+    #[wasm_bindgen]
+    pub fn depletion_age_months_for_run(&self, run: usize) -> Option<usize> {
+       self.runs[run].depletion_period().map(|period| self.runs[run].person.age_months(period))
+    }
+
+    #[wasm_bindgen]
+    pub fn total_shortfall_for_run(&self, run: usize) -> f64 {
+       self.runs[run].total_shortfall()
+    }
+
+    #[wasm_bindgen]
+    pub fn years_in_shortfall_for_run(&self, run: usize) -> f64 {
+       self.runs[run].years_in_shortfall()
+    }
+
+    #[wasm_bindgen]
+    pub fn shortfall_for_run(&self, run: usize) -> Float64Array {
+       unsafe {
+           Float64Array::view(&self.runs[run].shortfall)
+       }
+    }
+
+    // Average years spent unable to cover spending, among the runs that ran out
+    #[wasm_bindgen]
+    pub fn mean_years_in_shortfall_when_depleted(&self) -> f64 {
+        let depleted: Vec<f64> = self.runs.iter().filter(|r| r.depletion_period().is_some()).map(|r| r.years_in_shortfall()).collect();
+        if depleted.is_empty() {
+            0.0
+        } else {
+            depleted.iter().sum::<f64>() / depleted.len() as f64
+        }
+    }
+
+    // How often the scheduled expense was paid in full, out of the runs that lived long enough for
+    // it to come due
+    #[wasm_bindgen]
+    pub fn expense_funded_rate(&self, expense: usize) -> Ratio {
+        let due: Vec<bool> = self.runs.iter().filter_map(|r| r.expenses_funded[expense]).collect();
+
+        Ratio {
+            num: due.iter().filter(|funded| **funded).count(),
+            denom: due.len()
+        }
+    }
+
+    #[wasm_bindgen]
+    pub fn survivor_pension_for_run(&self, run: usize) -> f64 {
+       self.runs[run].survivor_pension
+    }
+
+    #[wasm_bindgen]
+    pub fn lifetime_taxes_for_run(&self, run: usize) -> f64 {
+       self.runs[run].lifetime_taxes
+    }
+
+    // Average lifetime taxes saved compared to a baseline simulation created with the same seed
+    // and count, e.g. one without Roth conversions, so each pair of runs shares the same market
+    // returns and lifespan
+    #[wasm_bindgen]
+    pub fn lifetime_taxes_saved(&self, baseline: &Simulation) -> f64 {
+        assert_eq!(self.runs.len(), baseline.runs.len());
+
+        let saved: f64 = self.runs.iter().zip(&baseline.runs).map(|(run, base)| base.lifetime_taxes - run.lifetime_taxes).sum();
+        saved / self.runs.len() as f64
+    }
+
     #[wasm_bindgen]
     pub fn get_account_balance_for_run(&self, run: usize, acct: usize) -> Float64Array {
        unsafe {
@@ -214,26 +657,82 @@ impl Simulation {
 }
 
 impl Simulation {
-    pub fn new<T: SeedableRng + Rng + Clone + std::fmt::Debug, U: TaxCollector + std::fmt::Debug>(seed: u64, count: usize, rates_source: RatesSourceHolder, sublength: usize, job_settings: JobSettings, person_settings: PersonSettings, career_periods: usize, tax_settings: TaxSettings) -> Simulation {
-        let runs: Vec<Run> = (0..count).map(|seed2| {
+    pub fn new<T: SeedableRng + Rng + Clone + std::fmt::Debug, U: TaxCollector + std::fmt::Debug>(settings: &SimulationSettings) -> Simulation {
+        Self::new_with::<T, U>(settings, &settings.career_settings, &settings.withdrawal_settings)
+    }
+
+    fn new_with<T: SeedableRng + Rng + Clone + std::fmt::Debug, U: TaxCollector + std::fmt::Debug>(settings: &SimulationSettings, career_settings: &CareerSettings, withdrawal_settings: &WithdrawalSettings) -> Simulation {
+        let runs: Vec<Run> = (0..settings.count).map(|seed2| {
             // TODO this seed stuff is kinda awful
-            let new_seed = (seed as usize * count) as u64 + (seed2 as u64);
-            // TODO figure out a way to avoid cloning tax_settings here
-            Run::execute::<T, U>(new_seed, rates_source.get_rates_source(), sublength, &job_settings, &person_settings, career_periods, tax_settings.clone())
+            let new_seed = (settings.seed as usize * settings.count) as u64 + (seed2 as u64);
+            Run::execute::<T, U>(new_seed, settings, career_settings, withdrawal_settings)
         }).collect();
 
         Simulation { runs }
     }
 
+    pub fn claiming_ages<T: SeedableRng + Rng + Clone + std::fmt::Debug, U: TaxCollector + std::fmt::Debug>(settings: &SimulationSettings) -> Option<ClaimingAgeTable> {
+        simplifying_assumption!("Couples aren't modeled, so the claiming age table only varies one \
+            person's claiming age rather than searching each combination of both spouses' ages, and \
+            leaves out spousal and survivor benefits.");
+        let social_security = settings.withdrawal_settings.social_security.as_ref()?;
+
+        let results = (62..=70).map(|age| {
+            let claiming_age_months = age * 12;
+            let withdrawal_settings = WithdrawalSettings { social_security: Some(social_security.with_claiming_age(claiming_age_months)), ..settings.withdrawal_settings.clone() };
+            let simulation = Self::new_with::<T, U>(settings, &settings.career_settings, &withdrawal_settings);
+
+            ClaimingAgeResult::new(claiming_age_months, &simulation)
+        }).collect();
+
+        Some(ClaimingAgeTable { results })
+    }
+
+    // Every pension with a lump sum offer is taken as an annuity in one simulation and as the lump
+    // sum in the other
+    pub fn compare_pension<T: SeedableRng + Rng + Clone + std::fmt::Debug, U: TaxCollector + std::fmt::Debug>(settings: &SimulationSettings, discount_rate: f64) -> Option<PensionComparison> {
+        if !settings.career_settings.offers_lump_sum() {
+            return None;
+        }
+
+        let annuity = Self::new_with::<T, U>(settings, &settings.career_settings.declining_lump_sums(), &settings.withdrawal_settings);
+        let lump_sum_simulation = Self::new::<T, U>(settings);
+
+        let pensions: Vec<(&Pension, f64)> = annuity.runs.iter()
+            .flat_map(|r| &r.pensions_at_retirement)
+            .filter_map(|p| p.lump_sum().map(|lump_sum| (p, lump_sum)))
+            .collect();
+        let mean_annuity_value = if pensions.is_empty() {
+            0.0
+        } else {
+            pensions.iter().map(|(p, _)| p.present_value(discount_rate, &settings.person_settings)).sum::<f64>() / pensions.len() as f64
+        };
+
+        // Never breaking even sorts last
+        let mut breakeven: Vec<Option<usize>> = pensions.iter().map(|(p, lump_sum)| p.breakeven_age_months(*lump_sum, discount_rate)).collect();
+        breakeven.sort_by_key(|age| age.unwrap_or(usize::MAX));
+
+        Some(PensionComparison {
+            annuity_success_rate: annuity.success_rate(),
+            lump_sum_success_rate: lump_sum_simulation.success_rate(),
+            mean_annuity_value,
+            breakeven_age_months: breakeven.get(breakeven.len() / 2).copied().flatten()
+        })
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::assets::{AssetAllocation,AccountSettings};
-    use crate::income::{Fica,RaiseSettings,AccountContributionSettings,AccountContributionSource,AccountContributionTaxability};
+    use crate::assets::{AssetAllocation,AccountSettings,FundedRatioRule};
+    use crate::income::{Fica,FicaSettings,RaiseSettings,AccountContributionSettings,AccountContributionSource,AccountContributionTaxability};
     use crate::rates::RatesSourceHolder;
     use crate::taxes::{MockTaxCollector,TaxResult,Money, TaxBracket};
     use crate::util::get_thread_local_rc;
+    use crate::util::tests::assert_vecfloat_absolute;
+    use crate::pension::{PensionCola, PensionSettings, SurvivorOption};
+    use crate::socialsecurity::SocialSecuritySettings;
+    use crate::withdrawal::{ConstantDollarAmount, DiscretionaryCut, VanguardDynamicSettings};
+    use assert_float_eq::*;
     use super::*;
 
     include!(concat!(env!("OUT_DIR"), "/test_rates.rs"));
@@ -247,20 +746,39 @@ mod tests {
                 Money::NonTaxable(amt) => TaxResult::new(0.0, amt)
             }
         });
+        null_tax.expect_total_taxes().returning(|| 0.0);
         null_tax
     }
 
+    fn new_run(rates: &Rc<Vec<Rate>>, lifespan: Lifespan, careerspan: Lifespan, age: usize) -> Run {
+        Run {
+            rates: Rc::clone(rates),
+            assets_adequate_periods: 0,
+            lifespan,
+            careerspan,
+            person: Person::new(lifespan, age, &[0.01]),
+            retirement_accounts: vec![],
+            lifetime_taxes: 0.0,
+            social_security_benefits: 0.0,
+            survivor_pension: 0.0,
+            pensions_at_retirement: vec![],
+            shortfall: vec![0.0; lifespan.periods()],
+            essential_shortfall: vec![0.0; lifespan.periods()],
+            expenses_funded: vec![]
+        }
+    }
+
     #[test]
     pub fn run_withadequate() {
         let rates = Rc::new(vec![Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5), Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5)]);
         let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.75, 2, 0.25));
 
         let account = AccountContributionSettings::new(AccountSettings::new(2048.0, asset_allocation), 0.25, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
-        let mut run = Run { rates: Rc::clone(&rates), assets_adequate_periods: 0, lifespan: Lifespan::new(6), careerspan: Lifespan::new(3), retirement_accounts: vec![] };
-        let job = JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account] ).create_job(Lifespan::new(6), Lifespan::new(3), rates);
+        let mut run = new_run(&rates, Lifespan::new(6), Lifespan::new(3), 60);
+        let job = JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account] ).create_job(Lifespan::new(6), Lifespan::new(3), 60 * 12, rates);
         let null_tax = get_null_tax();
         
-        run.populate(job, null_tax);
+        run.populate(job, null_tax, &WithdrawalRule::Orig.into());
 
         assert_eq!(run.retirement_accounts[0].balance(), &vec![2944.0, 4560.0, 5642.0, 4458.625, 4315.9453125, 3319.4384765625]);
         assert_eq!(run.assets_adequate_periods, 6);
@@ -272,16 +790,256 @@ mod tests {
         let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.75, 2, 0.25));
 
         let account = AccountContributionSettings::new(AccountSettings::new(1024.0, asset_allocation), 0.125, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
-        let mut run = Run { rates: Rc::clone(&rates), assets_adequate_periods: 0, lifespan: Lifespan::new(6), careerspan: Lifespan::new(3), retirement_accounts: vec![] };
-        let job = JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account] ).create_job(Lifespan::new(6), Lifespan::new(3), rates);
+        let mut run = new_run(&rates, Lifespan::new(6), Lifespan::new(3), 60);
+        let job = JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account] ).create_job(Lifespan::new(6), Lifespan::new(3), 60 * 12, rates);
         let null_tax = get_null_tax();
         
-        run.populate(job, null_tax);
+        run.populate(job, null_tax, &WithdrawalRule::Orig.into());
 
         assert_eq!(run.retirement_accounts[0].balance(), &vec![1472.0, 2280.0, 2821.0, 1205.3125, 0.0, 0.0]);
         assert_eq!(run.assets_adequate_periods, 4);
+        // The run keeps going after the money runs out
+        assert_eq!(run.depletion_period().unwrap().get(), 4);
+        assert_eq!(run.shortfall[5], 1792.0);
+        assert_eq!(run.years_in_shortfall(), 2.0 / 12.0);
+        // Totaled in today's dollars, after 50% inflation
+        assert_float_absolute_eq!(run.total_shortfall(), (run.shortfall[4] + 1792.0) / 1.5);
+    }
+
+    #[test]
+    pub fn run_roundingshortfall() {
+        let rates = Rc::new(vec![Rate::new(1.0, 1.0, 1.0); 6]);
+        let mut run = new_run(&rates, Lifespan::new(6), Lifespan::new(3), 60);
+
+        // Rounding error isn't a shortfall
+        run.shortfall[3] = 1e-9;
+        assert!(run.depletion_period().is_none());
+        assert_eq!(run.years_in_shortfall(), 0.0);
+
+        run.shortfall[4] = 0.5;
+        assert_eq!(run.depletion_period().unwrap().get(), 4);
+        assert_eq!(run.years_in_shortfall(), 1.0 / 12.0);
     }
     
+    #[test]
+    pub fn run_withfundedratio() {
+        let rates = Rc::new(vec![Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5), Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5)]);
+        let glide = AssetAllocation::new_linear_glide(1, 0.75, 2, 0.25);
+        // Pinned at 0% stocks regardless of the funded ratio, so retirement should be all bonds
+        let asset_allocation = Rc::new(AssetAllocation::new_funded_ratio(&glide, FundedRatioRule::new(1.0, 0.0, 2.0, 0.0, 0.0)));
+
+        let account = AccountContributionSettings::new(AccountSettings::new(2048.0, asset_allocation), 0.25, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
+        let mut run = new_run(&rates, Lifespan::new(6), Lifespan::new(3), 60);
+        let job = JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account] ).create_job(Lifespan::new(6), Lifespan::new(3), 60 * 12, rates);
+        let null_tax = get_null_tax();
+
+        run.populate(job, null_tax, &WithdrawalRule::Orig.into());
+
+        assert_eq!(run.retirement_accounts[0].balance(), &vec![2944.0, 4560.0, 5642.0, 4106.0, 3596.5, 2959.625]);
+        assert_eq!(run.assets_adequate_periods, 6);
+    }
+
+    #[test]
+    pub fn run_withconstantdollar() {
+        let rates = Rc::new(vec![Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5), Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5)]);
+        let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.75, 2, 0.25));
+
+        let account = AccountContributionSettings::new(AccountSettings::new(2048.0, asset_allocation), 0.25, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
+        let mut run = new_run(&rates, Lifespan::new(6), Lifespan::new(3), 60);
+        let job = JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account] ).create_job(Lifespan::new(6), Lifespan::new(3), 60 * 12, rates);
+        let null_tax = get_null_tax();
+
+        // 512/month in today's dollars is 768/month after the 50% inflation before retirement
+        run.populate(job, null_tax, &WithdrawalRule::ConstantDollar { amount: ConstantDollarAmount::Target(512.0 * 12.0) }.into());
+
+        assert_eq!(run.retirement_accounts[0].balance()[..4], [2944.0, 4560.0, 5642.0, 5994.625 - 768.0]);
+        assert_eq!(run.assets_adequate_periods, 6);
+    }
+
+    #[test]
+    pub fn run_withessentialshortfall() {
+        let rates = Rc::new(vec![Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5), Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5)]);
+        let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.75, 2, 0.25));
+
+        let account = AccountContributionSettings::new(AccountSettings::new(1024.0, asset_allocation), 0.125, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
+        let mut run = new_run(&rates, Lifespan::new(6), Lifespan::new(3), 60);
+        let job = JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account] ).create_job(Lifespan::new(6), Lifespan::new(3), 60 * 12, rates);
+        let null_tax = get_null_tax();
+
+        // 1000/month of essential spending is 1500 after inflation, out of the 1792 withdrawn
+        let plan = SpendingPlan::new_from_js(12000.0, vec![], vec![], 0.0, vec![], vec![]);
+        let settings = WithdrawalSettings { spending_plan: Some(plan), ..WithdrawalRule::Orig.into() };
+        run.populate(job, null_tax, &settings);
+
+        assert_eq!(run.shortfall[5], 1792.0);
+        assert_eq!(run.essential_shortfall[5], 1500.0);
+    }
+
+    #[test]
+    pub fn run_withscheduledexpenses() {
+        let rates = Rc::new(vec![Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5), Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5)]);
+        let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.75, 2, 0.25));
+
+        let account = AccountContributionSettings::new(AccountSettings::new(2048.0, asset_allocation), 0.25, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
+        let mut run = new_run(&rates, Lifespan::new(6), Lifespan::new(3), 60);
+        let job = JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account] ).create_job(Lifespan::new(6), Lifespan::new(3), 60 * 12, rates);
+        let null_tax = get_null_tax();
+
+        let mut plan = SpendingPlan::default();
+        plan.add_expense(ScheduledExpense::once(1000.0, 60, SpendingPriority::Essential));
+        plan.add_expense(ScheduledExpense::once(5000.0, 60, SpendingPriority::Discretionary));
+        plan.add_expense(ScheduledExpense::once(5000.0, 61, SpendingPriority::Discretionary));
+        let settings = WithdrawalSettings { spending_plan: Some(plan), ..WithdrawalRule::Orig.into() };
+        run.populate(job, null_tax, &settings);
+
+        // The first expense is paid out of savings, leaving nothing for the second
+        assert_eq!(run.retirement_accounts[0].balance()[0], 0.0);
+        assert_eq!(run.expenses_funded, vec![Some(true), Some(false), None]);
+
+        // What savings couldn't pay is a shortfall, though not an essential one
+        assert!(run.shortfall[0] > 0.0);
+        assert_eq!(run.essential_shortfall[0], 0.0);
+        assert_eq!(run.depletion_period().unwrap().get(), 0);
+        assert_eq!(run.assets_adequate_periods, 0);
+    }
+
+    #[test]
+    pub fn run_withunfundedessentialexpense() {
+        let rates = Rc::new(vec![Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5), Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5)]);
+        let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.75, 2, 0.25));
+
+        let account = AccountContributionSettings::new(AccountSettings::new(2048.0, asset_allocation), 0.25, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
+        let mut run = new_run(&rates, Lifespan::new(6), Lifespan::new(3), 60);
+        let job = JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account] ).create_job(Lifespan::new(6), Lifespan::new(3), 60 * 12, rates);
+        let null_tax = get_null_tax();
+
+        let mut plan = SpendingPlan::default();
+        plan.add_expense(ScheduledExpense::once(5000.0, 60, SpendingPriority::Essential));
+        let settings = WithdrawalSettings { spending_plan: Some(plan), ..WithdrawalRule::Orig.into() };
+        run.populate(job, null_tax, &settings);
+
+        // An essential expense savings can't cover before retirement is an essential shortfall
+        assert!(run.shortfall[0] > 0.0);
+        assert_eq!(run.essential_shortfall[0], run.shortfall[0]);
+        assert_eq!(run.expenses_funded, vec![Some(false)]);
+        assert_eq!(run.assets_adequate_periods, 0);
+    }
+
+    #[test]
+    pub fn run_withsocialsecurity() {
+        let rates = Rc::new(vec![Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5), Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5)]);
+        let run_with_settings = |settings: WithdrawalSettings| {
+            let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.75, 2, 0.25));
+            let account = AccountContributionSettings::new(AccountSettings::new(2048.0, asset_allocation), 0.25, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
+            let mut run = new_run(&rates, Lifespan::new(6), Lifespan::new(3), 67);
+            let job = JobSettings::new(2048.0, Fica::Participant(FicaSettings::irs_2023()), RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account] ).create_job(Lifespan::new(6), Lifespan::new(3), 67 * 12, Rc::clone(&rates));
+            run.populate(job, get_null_tax(), &settings);
+            run
+        };
+
+        // A benefit of 90% of 40000 / 12 covers the take-home pay, so the portfolio is left alone,
+        // same as when nothing is spent
+        let social_security = SocialSecuritySettings::new(67 * 12, 67 * 12, 10000.0, 20000.0, vec![40000.0; 34]);
+        let with_benefits = run_with_settings(WithdrawalSettings { social_security: Some(social_security.clone()), ..WithdrawalRule::Orig.into() });
+        let spending_nothing = run_with_settings(WithdrawalSettings { spending_plan: Some(SpendingPlan::default()), ..WithdrawalRule::SpendingPlan(DiscretionaryCut::none()).into() });
+
+        assert_eq!(with_benefits.assets_adequate_periods, 6);
+        assert_eq!(with_benefits.retirement_accounts[0].balance(), spending_nothing.retirement_accounts[0].balance());
+
+        // A rule based on the portfolio withdraws the same with or without benefits, which are
+        // spent on top
+        let vanguard = WithdrawalRule::VanguardDynamic(VanguardDynamicSettings::new(0.5, 0.05, -0.025));
+        let vanguard_with_benefits = run_with_settings(WithdrawalSettings { social_security: Some(social_security), ..vanguard.clone().into() });
+        let vanguard_without_benefits = run_with_settings(vanguard.into());
+
+        assert_vecfloat_absolute(vanguard_with_benefits.retirement_accounts[0].balance().clone(), vanguard_without_benefits.retirement_accounts[0].balance().clone());
+        assert!(vanguard_with_benefits.retirement_accounts[0].balance()[5] < spending_nothing.retirement_accounts[0].balance()[5]);
+    }
+
+    #[test]
+    pub fn run_withpension() {
+        let rates = Rc::new(vec![Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5), Rate::new(1.25, 1.0, 1.0), Rate::new(1.5, 1.25, 1.0), Rate::new(0.75, 1.25, 1.5)]);
+        let run_with_settings = |job_settings: JobSettings, settings: WithdrawalSettings| {
+            let mut run = new_run(&rates, Lifespan::new(6), Lifespan::new(3), 65);
+            let job = job_settings.create_job(Lifespan::new(6), Lifespan::new(3), 65 * 12, Rc::clone(&rates));
+            run.populate(job, get_null_tax(), &settings);
+            run
+        };
+        let job_settings = || {
+            let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.75, 2, 0.25));
+            let account = AccountContributionSettings::new(AccountSettings::new(2048.0, asset_allocation), 0.25, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
+            JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account])
+        };
+
+        // 24576 a year for a quarter of a year at a 1000% multiplier pays 5120 a month, which covers
+        // the take-home pay, so the portfolio is left alone, same as when nothing is spent
+        let pension = PensionSettings::new(10.0, 3, 65, 0.05, PensionCola::None, SurvivorOption::JointAndFull, 0.0);
+        let with_pension = run_with_settings(job_settings().with_pension(pension), WithdrawalRule::Orig.into());
+        let spending_nothing = run_with_settings(job_settings(), WithdrawalSettings { spending_plan: Some(SpendingPlan::default()), ..WithdrawalRule::SpendingPlan(DiscretionaryCut::none()).into() });
+
+        assert_eq!(with_pension.assets_adequate_periods, 6);
+        assert_eq!(with_pension.retirement_accounts[0].balance(), spending_nothing.retirement_accounts[0].balance());
+        assert_eq!(with_pension.survivor_pension, 5120.0);
+
+        // Taking a lump sum instead rolls it over into a new account at the end of the career
+        let with_lump_sum = run_with_settings(job_settings().with_pension(pension.with_lump_sum(100000.0)), WithdrawalRule::Orig.into());
+        assert_eq!(with_lump_sum.retirement_accounts.len(), 2);
+        assert_eq!(with_lump_sum.retirement_accounts[1].balance()[2], 100000.0);
+        assert_eq!(with_lump_sum.retirement_accounts[1].tax_treatment(), AccountTaxTreatment::TaxDeferred);
+        assert!(with_lump_sum.pensions_at_retirement.is_empty());
+        assert_eq!(with_lump_sum.survivor_pension, 0.0);
+
+        // Without any other accounts it still gets an account of its own
+        let no_accounts = JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![]).with_pension(pension.with_lump_sum(100000.0));
+        let lump_sum_only = run_with_settings(no_accounts, WithdrawalRule::Orig.into());
+        assert_eq!(lump_sum_only.retirement_accounts.len(), 1);
+        assert_eq!(lump_sum_only.retirement_accounts[0].balance()[2], 100000.0);
+        assert_eq!(lump_sum_only.retirement_accounts[0].tax_treatment(), AccountTaxTreatment::TaxDeferred);
+
+        // Declining the offer keeps the annuity
+        let declined = run_with_settings(job_settings().with_pension(pension.with_lump_sum(100000.0).declining_lump_sum()), WithdrawalRule::Orig.into());
+        assert_eq!(declined.retirement_accounts[0].balance(), with_pension.retirement_accounts[0].balance());
+        assert_eq!(declined.pensions_at_retirement[0].lump_sum(), Some(100000.0));
+
+        // With the pension covering all the spending, the portfolio is fully funded and a funded
+        // ratio allocation holds it at its target, same as one pinned there
+        let funded_ratio_job = |rule: FundedRatioRule| {
+            let glide = AssetAllocation::new_linear_glide(1, 0.75, 2, 0.25);
+            let asset_allocation = Rc::new(AssetAllocation::new_funded_ratio(&glide, rule));
+            let account = AccountContributionSettings::new(AccountSettings::new(2048.0, asset_allocation), 0.25, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
+            JobSettings::new(2048.0, Fica::Exempt, RaiseSettings {amount: 1.0, adjust_for_inflation: false}, vec![account]).with_pension(pension)
+        };
+        let funded = run_with_settings(funded_ratio_job(FundedRatioRule::new(1.0, 0.0, 2.0, 1.0, 0.0)), WithdrawalRule::Orig.into());
+        let pinned = run_with_settings(funded_ratio_job(FundedRatioRule::new(1.0, 1.0, 2.0, 1.0, 0.0)), WithdrawalRule::Orig.into());
+        assert_eq!(funded.retirement_accounts[0].balance(), pinned.retirement_accounts[0].balance());
+    }
+
+    #[test]
+    pub fn simulation_pensioncomparison() {
+        let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.6, 30 * 12, 0.4));
+        let account_settings = AccountSettings::new(200000.0, asset_allocation);
+        let account_contribution_settings = AccountContributionSettings::new(account_settings, 0.1, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
+        let pension = PensionSettings::new(0.02, 3, 65, 0.05, PensionCola::None, SurvivorOption::SingleLife, 0.0).with_lump_sum(50000.0);
+        let job_settings = JobSettings::new(90000.0 / 12.0, Fica::Exempt, RaiseSettings { amount: 1.0, adjust_for_inflation: false }, vec![account_contribution_settings]).with_pension(pension);
+        let person_settings = PersonSettings::new(60, 0, get_thread_local_rc(&TEST_DEATH_BUILTIN).clone());
+        let tax_settings = TaxSettings::new(vec![TaxBracket { floor: 0.0, rate: 0.15 }], true, 12950.0, true);
+        let settings = SimulationSettings::new(1337, 20, RatesSourceHolder::new_from_custom(Vec::from(TEST_RATES_BUILTIN)), 12, person_settings, 5 * 12, tax_settings);
+        let comparison = Simulation::compare_pension::<rand_pcg::Pcg64Mcg, Tax>(&settings.with_job(job_settings.clone()), 0.05).unwrap();
+
+        assert_eq!(comparison.annuity_success_rate.denom, 20);
+        assert_eq!(comparison.lump_sum_success_rate.denom, 20);
+
+        // 90000 * 5 years * 2% is 750 a month starting at 65, with no raises, so the undiscounted
+        // breakeven would be 50000 / 750 = 66.67 payments in
+        assert!(comparison.mean_annuity_value > 50000.0);
+        assert!(comparison.breakeven_age_months().unwrap() > 65 * 12 + 67);
+
+        // Nothing to compare without a lump sum offer
+        let annuity_only = job_settings.with_pension(PensionSettings::new(0.02, 3, 65, 0.05, PensionCola::None, SurvivorOption::SingleLife, 0.0));
+        let settings = SimulationSettings::new(1337, 20, RatesSourceHolder::new_from_custom(Vec::from(TEST_RATES_BUILTIN)), 12, PersonSettings::new(60, 0, get_thread_local_rc(&TEST_DEATH_BUILTIN).clone()), 5 * 12, TaxSettings::new(vec![], false, 0.0, false));
+        assert!(Simulation::compare_pension::<rand_pcg::Pcg64Mcg, Tax>(&settings.with_job(annuity_only), 0.05).is_none());
+    }
+
     #[test]
     pub fn simulation_regression1() {
         let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.83, (110 - 27) * 12, 0.0));
@@ -297,7 +1055,8 @@ mod tests {
         let person_settings = PersonSettings::new(27, 0, death_rates);
         let brackets = vec![(0.0, 0.1), (10275.0, 0.12), (41775.0, 0.22), (89075.0, 0.24), (170050.0, 0.32), (215950.0, 0.35), (539900.0, 0.37)].iter().map(|b| { TaxBracket { floor: b.0, rate: b.1 } }).collect();
         let tax_settings = TaxSettings::new(brackets, true, 12950.0, true );
-        let simulation = Simulation::new::<rand_pcg::Pcg64Mcg, Tax>(1337, 100, RatesSourceHolder::new_from_custom(Vec::from(TEST_RATES_BUILTIN)), 12, job_settings, person_settings, (65 - 27) * 12, tax_settings);
+        let settings = SimulationSettings::new(1337, 100, RatesSourceHolder::new_from_custom(Vec::from(TEST_RATES_BUILTIN)), 12, person_settings, (65 - 27) * 12, tax_settings).with_job(job_settings);
+        let simulation = Simulation::new::<rand_pcg::Pcg64Mcg, Tax>(&settings);
 
         assert_eq!(simulation.success_rate().num, 48);
         assert_eq!(simulation.success_rate().denom, 100);
@@ -315,6 +1074,37 @@ mod tests {
         
     }
 
+    #[test]
+    pub fn simulation_claimingages() {
+        let asset_allocation = Rc::new(AssetAllocation::new_linear_glide(1, 0.6, 30 * 12, 0.4));
+        let account_settings = AccountSettings::new(500000.0, asset_allocation);
+        let account_contribution_settings = AccountContributionSettings::new(account_settings, 0.1, AccountContributionSource::Employee, AccountContributionTaxability::PreTax);
+        let job_settings = JobSettings::new(90000.0 / 12.0, Fica::Participant(FicaSettings::irs_2023()), RaiseSettings { amount: 1.0, adjust_for_inflation: true }, vec![account_contribution_settings]);
+        let person_settings = PersonSettings::new(60, 0, get_thread_local_rc(&TEST_DEATH_BUILTIN).clone());
+        let tax_settings = TaxSettings::new(vec![TaxBracket { floor: 0.0, rate: 0.15 }], true, 12950.0, true);
+        let withdrawal_settings = WithdrawalSettings { social_security: Some(SocialSecuritySettings::ssa_2023(67 * 12, vec![60000.0; 30])), ..WithdrawalRule::Orig.into() };
+        let settings = SimulationSettings::new(1337, 20, RatesSourceHolder::new_from_custom(Vec::from(TEST_RATES_BUILTIN)), 12, person_settings, 5 * 12, tax_settings)
+            .with_job(job_settings)
+            .with_withdrawal_settings(withdrawal_settings);
+        let table = Simulation::claiming_ages::<rand_pcg::Pcg64Mcg, Tax>(&settings).unwrap();
+
+        assert_eq!(table.count(), 9);
+        assert_eq!(table.get(0).claiming_age_months, 62 * 12);
+        assert_eq!(table.get(8).claiming_age_months, 70 * 12);
+        assert_eq!(table.get(0).success_rate.denom, 20);
+
+        // Everyone lives to 62 and sees the same returns, so claiming early always gets something
+        assert!(table.get(0).mean_lifetime_benefits > 0.0);
+        for objective in [ClaimingAgeObjective::SuccessRate, ClaimingAgeObjective::LifetimeBenefits, ClaimingAgeObjective::TerminalWealth] {
+            let best = table.best(objective);
+            assert!((0..table.count()).all(|i| table.get(i).score(objective) <= best.score(objective)));
+        }
+
+        // Nothing to sweep without Social Security
+        let settings = SimulationSettings::new(1337, 20, RatesSourceHolder::new_from_custom(Vec::from(TEST_RATES_BUILTIN)), 12, PersonSettings::new(60, 0, get_thread_local_rc(&TEST_DEATH_BUILTIN).clone()), 5 * 12, TaxSettings::new(vec![], false, 0.0, false));
+        assert!(Simulation::claiming_ages::<rand_pcg::Pcg64Mcg, Tax>(&settings).is_none());
+    }
+
     #[test]
     pub fn period_sub() {
         let period = Period::new(1);
@@ -0,0 +1,292 @@
+use wasm_bindgen::prelude::*;
+
+use crate::montecarlo::Period;
+use crate::person::PersonSettings;
+use crate::rates::Rate;
+use crate::simplifying_assumption;
+
+// How far out to price an annuity, well past any realistic lifespan
+const MAX_PAYMENT_MONTHS: usize = 100 * 12;
+
+#[derive(Copy,Clone,Debug)]
+pub enum PensionCola {
+    None,
+    // The same raise every year, e.g. 0.02
+    Fixed(f64),
+    // Follows the last year's inflation up to the cap, e.g. 0.03, and never cuts the benefit
+    CpiCapped(f64)
+}
+
+#[wasm_bindgen]
+pub struct PensionColaJS {
+    cola: PensionCola
+}
+
+#[wasm_bindgen]
+impl PensionColaJS {
+    #[wasm_bindgen]
+    pub fn none() -> PensionColaJS {
+        PensionColaJS { cola: PensionCola::None }
+    }
+
+    #[wasm_bindgen]
+    pub fn fixed(rate: f64) -> PensionColaJS {
+        PensionColaJS { cola: PensionCola::Fixed(rate) }
+    }
+
+    #[wasm_bindgen]
+    pub fn cpi_capped(cap: f64) -> PensionColaJS {
+        PensionColaJS { cola: PensionCola::CpiCapped(cap) }
+    }
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+#[wasm_bindgen]
+pub enum SurvivorOption {
+    SingleLife,
+    JointAndHalf,
+    JointAndFull
+}
+
+impl SurvivorOption {
+    // Share of the retiree's benefit that keeps going to the survivor
+    fn survivor_pct(&self) -> f64 {
+        match self {
+            SurvivorOption::SingleLife => 0.0,
+            SurvivorOption::JointAndHalf => 0.5,
+            SurvivorOption::JointAndFull => 1.0
+        }
+    }
+}
+
+// Final average pay is the average annual gross pay over the last final_average_years worked.
+// Benefits start at retirement, reduced by early_reduction for each year before
+// normal_retirement_age.  A joint-and-survivor option reduces the retiree's benefit by
+// joint_reduction, which depends on the plan and the spouse's age.
+simplifying_assumption!("Couples aren't modeled, so a survivor option only reduces the retiree's \
+    pension, and the survivor's benefit is reported rather than simulated.");
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct PensionSettings {
+    multiplier: f64,
+    final_average_years: usize,
+    normal_retirement_age: usize,
+    early_reduction: f64,
+    cola: PensionCola,
+    survivor: SurvivorOption,
+    joint_reduction: f64,
+    // Nominal amount offered at retirement instead of the annuity, if there's an offer
+    lump_sum: Option<f64>,
+    take_lump_sum: bool
+}
+
+#[wasm_bindgen]
+impl PensionSettings {
+    #[wasm_bindgen(constructor)]
+    pub fn new_from_js(multiplier: f64, final_average_years: usize, normal_retirement_age: usize, early_reduction: f64, cola: PensionColaJS, survivor: SurvivorOption, joint_reduction: f64) -> PensionSettings {
+        Self::new(multiplier, final_average_years, normal_retirement_age, early_reduction, cola.cola, survivor, joint_reduction)
+    }
+
+    // Takes the lump sum offered instead of the annuity
+    #[wasm_bindgen]
+    pub fn with_lump_sum(self, lump_sum: f64) -> PensionSettings {
+        PensionSettings { lump_sum: Some(lump_sum), take_lump_sum: true, ..self }
+    }
+}
+
+impl PensionSettings {
+    pub fn new(multiplier: f64, final_average_years: usize, normal_retirement_age: usize, early_reduction: f64, cola: PensionCola, survivor: SurvivorOption, joint_reduction: f64) -> PensionSettings {
+        assert!(final_average_years > 0);
+
+        PensionSettings { multiplier, final_average_years, normal_retirement_age, early_reduction, cola, survivor, joint_reduction, lump_sum: None, take_lump_sum: false }
+    }
+
+    pub fn lump_sum(&self) -> Option<f64> {
+        self.lump_sum
+    }
+
+    // Keeps any lump sum offer, but takes the annuity instead
+    pub fn declining_lump_sum(&self) -> PensionSettings {
+        PensionSettings { take_lump_sum: false, ..*self }
+    }
+
+    // gross_income is the monthly pay for every period worked, and the pension starts in the
+    // retirement period
+    pub fn create_pension(&self, gross_income: &[f64], retirement: usize, retirement_age_months: usize) -> Pension {
+        let final_months = usize::min(self.final_average_years * 12, gross_income.len());
+        let final_average_pay = if final_months > 0 {
+            gross_income[gross_income.len() - final_months..].iter().sum::<f64>() * 12.0 / final_months as f64
+        } else {
+            0.0
+        };
+        let years_of_service = gross_income.len() as f64 / 12.0;
+
+        let years_early = (self.normal_retirement_age * 12).saturating_sub(retirement_age_months) as f64 / 12.0;
+        let early_adjustment = f64::max(1.0 - years_early * self.early_reduction, 0.0);
+        let survivor_adjustment = if self.survivor == SurvivorOption::SingleLife { 1.0 } else { 1.0 - self.joint_reduction };
+
+        let annual_benefit = final_average_pay * years_of_service * self.multiplier * early_adjustment * survivor_adjustment;
+        Pension {
+            cola: self.cola,
+            survivor: self.survivor,
+            start: retirement,
+            start_age_months: retirement_age_months,
+            monthly_benefit: annual_benefit / 12.0,
+            lump_sum: self.lump_sum,
+            take_lump_sum: self.take_lump_sum
+        }
+    }
+}
+
+#[derive(Clone,Debug)]
+pub struct Pension {
+    cola: PensionCola,
+    survivor: SurvivorOption,
+    start: usize,
+    start_age_months: usize,
+    monthly_benefit: f64,
+    lump_sum: Option<f64>,
+    take_lump_sum: bool
+}
+
+impl Pension {
+    pub fn lump_sum(&self) -> Option<f64> {
+        self.lump_sum
+    }
+
+    // The lump sum, if it's taken instead of the annuity
+    pub fn lump_sum_taken(&self) -> Option<f64> {
+        self.lump_sum.filter(|_| self.take_lump_sum)
+    }
+
+    // Payments from the start of the pension on, discounted back to the start at the annual
+    // discount rate
+    fn discounted_payments(&self, discount_rate: f64) -> impl Iterator<Item = f64> + '_ {
+        simplifying_assumption!("Annuities are priced as if CPI-capped COLAs always hit their cap.");
+        let annual_raise = match self.cola {
+            PensionCola::None => 1.0,
+            PensionCola::Fixed(rate) => 1.0 + rate,
+            PensionCola::CpiCapped(cap) => 1.0 + cap
+        };
+
+        (0..MAX_PAYMENT_MONTHS).map(move |month| {
+            let years = (month / 12) as i32;
+            self.monthly_benefit * annual_raise.powi(years) / (1.0 + discount_rate).powf(month as f64 / 12.0)
+        })
+    }
+
+    // Present value at the start of the pension, weighting each payment by the chance of living to
+    // get it
+    pub fn present_value(&self, discount_rate: f64, person_settings: &PersonSettings) -> f64 {
+        let mut alive = 1.0;
+
+        self.discounted_payments(discount_rate).enumerate().map(|(month, payment)| {
+            let value = payment * alive;
+            alive *= person_settings.monthly_survival(self.start_age_months + month);
+            value
+        }).sum()
+    }
+
+    // Age in months when the discounted payments received add up to the lump sum, if they ever do
+    pub fn breakeven_age_months(&self, lump_sum: f64, discount_rate: f64) -> Option<usize> {
+        let mut received = 0.0;
+
+        self.discounted_payments(discount_rate).position(|payment| {
+            received += payment;
+            received >= lump_sum
+        }).map(|month| self.start_age_months + month)
+    }
+
+    // Has to be called for every period from the start of the pension on, so COLAs build up
+    pub fn payment(&mut self, period: Period, rates: &[Rate]) -> f64 {
+        let months = period.get() - self.start;
+        if months > 0 && months.is_multiple_of(12) {
+            self.monthly_benefit *= match self.cola {
+                PensionCola::None => 1.0,
+                PensionCola::Fixed(rate) => 1.0 + rate,
+                PensionCola::CpiCapped(cap) => {
+                    let inflation: f64 = rates[period.get() - 12..period.get()].iter().map(|r| r.inflation()).product();
+                    inflation.clamp(1.0, 1.0 + cap)
+                }
+            };
+        }
+
+        self.monthly_benefit
+    }
+
+    // What the survivor would get each month, as of the last payment
+    pub fn survivor_benefit(&self) -> f64 {
+        self.monthly_benefit * self.survivor.survivor_pct()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use assert_float_eq::*;
+
+    use super::*;
+
+    #[test]
+    pub fn pensionsettings_createpension() {
+        // 20 years at 4000 a month, then 5 years at 6000
+        let mut gross_income = vec![4000.0; 20 * 12];
+        gross_income.extend([6000.0; 5 * 12]);
+
+        // 72000 * 25 years * 1.5%
+        let settings = PensionSettings::new(0.015, 3, 65, 0.05, PensionCola::None, SurvivorOption::SingleLife, 0.0);
+        assert_float_absolute_eq!(settings.create_pension(&gross_income, 300, 65 * 12).payment(Period::new(300), &[]), 2250.0);
+
+        // Averaging the last 10 years, retiring two and a half years early
+        let settings = PensionSettings::new(0.015, 10, 65, 0.05, PensionCola::None, SurvivorOption::SingleLife, 0.0);
+        assert_float_absolute_eq!(settings.create_pension(&gross_income, 300, 62 * 12 + 6).payment(Period::new(300), &[]), 1875.0 * 0.875);
+
+        let settings = PensionSettings::new(0.015, 3, 65, 0.05, PensionCola::None, SurvivorOption::JointAndHalf, 0.1);
+        let pension = settings.create_pension(&gross_income, 300, 65 * 12);
+        assert_float_absolute_eq!(pension.monthly_benefit, 2025.0);
+        assert_float_absolute_eq!(pension.survivor_benefit(), 1012.5);
+    }
+
+    #[test]
+    pub fn pension_payment_cola() {
+        // 5% inflation in the first year and 1% in the second
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 36];
+        rates[1] = Rate::new(1.0, 1.0, 1.05);
+        rates[13] = Rate::new(1.0, 1.0, 1.01);
+        let pension = |cola| Pension { cola, survivor: SurvivorOption::SingleLife, start: 0, start_age_months: 65 * 12, monthly_benefit: 1000.0, lump_sum: None, take_lump_sum: false };
+
+        let payments = |mut pension: Pension| (0..36).map(|period| pension.payment(Period::new(period), &rates)).collect::<Vec<f64>>();
+
+        let flat = payments(pension(PensionCola::None));
+        assert_eq!(flat[35], 1000.0);
+
+        let fixed = payments(pension(PensionCola::Fixed(0.02)));
+        assert_eq!(fixed[11], 1000.0);
+        assert_float_absolute_eq!(fixed[12], 1020.0);
+        assert_float_absolute_eq!(fixed[24], 1040.4);
+
+        let capped = payments(pension(PensionCola::CpiCapped(0.03)));
+        assert_float_absolute_eq!(capped[12], 1030.0);
+        assert_float_absolute_eq!(capped[24], 1040.3);
+    }
+
+    #[test]
+    pub fn pension_presentvalue_breakeven() {
+        let pension = Pension { cola: PensionCola::Fixed(0.1), survivor: SurvivorOption::SingleLife, start: 0, start_age_months: 65 * 12, monthly_benefit: 1000.0, lump_sum: None, take_lump_sum: false };
+
+        // Certain death in the first month at 66, after a year of payments and one more with the COLA
+        let mut death_rates = vec![0.0; 66];
+        death_rates.push(1.0);
+        let person_settings = PersonSettings::new(40, 0, Rc::from(death_rates));
+        assert_float_absolute_eq!(pension.present_value(0.0, &person_settings), 13100.0);
+
+        // 12000 in the first year, then 13200 a year
+        assert_eq!(pension.breakeven_age_months(12000.0, 0.0), Some(65 * 12 + 11));
+        assert_eq!(pension.breakeven_age_months(25200.0, 0.0), Some(66 * 12 + 11));
+        assert_eq!(pension.breakeven_age_months(f64::INFINITY, 0.0), None);
+
+        // Discounting at the same rate as the COLA stretches the breakeven out
+        assert_eq!(pension.breakeven_age_months(25200.0, 0.1), Some(67 * 12 + 2));
+    }
+}
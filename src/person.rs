@@ -1,6 +1,6 @@
 use rand::prelude::*;
 use wasm_bindgen::prelude::*;
-use crate::montecarlo::Lifespan;
+use crate::montecarlo::{Lifespan, Period};
 use crate::util::get_thread_local_rc;
 use std::rc::Rc;
 
@@ -16,17 +16,22 @@ pub enum Gender {
 pub struct PersonSettings {
     age_years: usize,
     age_months: usize,
-    annual_death_rates: Rc<[f64]>
+    annual_death_rates: Rc<[f64]>,
+    life_expectancy: Rc<[f64]>
 }
 
-#[derive(Debug)]
+#[derive(Clone,Debug)]
 pub struct Person {
-    lifespan: Lifespan
+    lifespan: Lifespan,
+    starting_age_months: usize,
+    life_expectancy: Rc<[f64]>
 }
 
 impl PersonSettings {
     pub fn new(age_years: usize, age_months: usize, annual_death_rates: Rc<[f64]>) -> PersonSettings {
-        PersonSettings { age_years, age_months, annual_death_rates }
+        let life_expectancy = Rc::from(life_expectancy::remaining_years_by_age(&annual_death_rates));
+
+        PersonSettings { age_years, age_months, annual_death_rates, life_expectancy }
     }
 
     pub fn create_person<R: Rng>(&self, rng: &mut R) -> Person {
@@ -34,9 +39,18 @@ impl PersonSettings {
         let lifespan = life_expectancy::calculate_periods(rng, &self.annual_death_rates[self.age_years..], self.age_months);
 
         Person { 
-            lifespan: Lifespan::new(lifespan)
+            lifespan: Lifespan::new(lifespan),
+            starting_age_months: self.age_years * 12 + self.age_months,
+            life_expectancy: Rc::clone(&self.life_expectancy)
         }
     }
+
+    // Chance of living through the month at the given age.  Ages past the end of the table keep
+    // dying at the last rate in the table.
+    pub fn monthly_survival(&self, age_months: usize) -> f64 {
+        let annual_death = self.annual_death_rates[std::cmp::min(age_months / 12, self.annual_death_rates.len() - 1)];
+        (1.0 - annual_death).powf(1.0 / 12.0)
+    }
 }
 
 #[wasm_bindgen]
@@ -60,14 +74,34 @@ impl PersonSettings {
 }
 
 impl Person {
+    #[cfg(test)]
+    pub fn new(lifespan: Lifespan, age_years: usize, annual_death_rates: &[f64]) -> Person {
+        let life_expectancy = Rc::from(life_expectancy::remaining_years_by_age(annual_death_rates));
+
+        Person { lifespan, starting_age_months: age_years * 12, life_expectancy }
+    }
+
     pub fn lifespan(&self) -> Lifespan {
         self.lifespan
     }
+
+    pub fn age_months(&self, period: Period) -> usize {
+        self.starting_age_months + period.get()
+    }
+
+    // Expected years left to live, judged from the mortality table rather than this person's
+    // actual (simulated) lifespan
+    pub fn remaining_life_expectancy(&self, period: Period) -> f64 {
+        let age_years = self.age_months(period) / 12;
+
+        self.life_expectancy[std::cmp::min(age_years, self.life_expectancy.len() - 1)]
+    }
 }
 
 mod life_expectancy {
     use std::cmp;    
     use rand::prelude::*;
+    use crate::simplifying_assumption;
 
     include!(concat!(env!("OUT_DIR"), "/death_female.rs"));
     include!(concat!(env!("OUT_DIR"), "/death_male.rs"));
@@ -90,6 +124,30 @@ mod life_expectancy {
         ret
     }
 
+    // Remaining life expectancy in years at each age in the table.  Ages past the end of the table
+    // keep dying at the last rate in the table.
+    pub fn remaining_years_by_age(annual_death: &[f64]) -> Vec<f64> {
+        assert!(!annual_death.is_empty());
+
+        (0..annual_death.len()).map(|age| {
+            let mut alive = 1.0;
+            let mut years = 0.0;
+
+            // Cap how far we look in case the table ends with a death rate of 0
+            for year in age..annual_death.len() + 200 {
+                alive *= 1.0 - annual_death[cmp::min(year, annual_death.len() - 1)];
+                years += alive;
+
+                if alive < 1e-9 {
+                    break;
+                }
+            }
+
+            simplifying_assumption!("Deaths happen halfway through the year on average");
+            years + 0.5
+        }).collect()
+    }
+
     pub fn calculate_periods<R: Rng>(rng: &mut R, annual_death: &[f64], offset: usize) -> usize {
         let life_rates = convert_annual_death_to_monthly_life(annual_death, offset);
 
@@ -130,6 +188,21 @@ mod life_expectancy {
             ]);
         }
 
+        #[test]
+        fn remainingyearsbyage() {
+            let ret = remaining_years_by_age(&[0.5, 0.5, 1.0]);
+
+            assert_vecfloat_absolute(ret, vec![1.25, 1.0, 0.5]);
+        }
+
+        #[test]
+        fn remainingyearsbyage_pasttable() {
+            // Constant 50% mortality forever gives 1/2 + 1/4 + 1/8 + ...
+            let ret = remaining_years_by_age(&[0.5]);
+
+            assert_vecfloat_absolute(ret, vec![1.5]);
+        }
+
         // TODO come up with a good way to actually unit test these functions which depend on RNG
         #[test]
         fn calculateperiods_regression1() {
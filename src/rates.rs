@@ -3,6 +3,8 @@ use rand::prelude::*;
 use serde::Deserialize;
 use std::{cmp::min, cell::{RefCell, Ref}};
 
+use crate::montecarlo::Period;
+
 #[derive(Copy, Clone, Debug, PartialEq, Deserialize)]
 pub struct Rate {
     stocks: f64,
@@ -17,6 +19,11 @@ impl Rate {
     pub fn inflation(&self) -> f64 { self.inflation }
 }
 
+// Inflation compounded from the start of the simulation up to, but not including, the given period
+pub fn cumulative_inflation(rates: &[Rate], period: Period) -> f64 {
+    rates[..period.get()].iter().map(|r| r.inflation()).product()
+}
+
 include!(concat!(env!("OUT_DIR"), "/rates.rs"));
 
 fn generate_rates_with_distribution<T: Rng + std::fmt::Debug, U: Distribution<u64> + std::fmt::Debug>(mut rng: T, rates_in: &[Rate], sublength: usize, length: usize, dist: U) -> Vec<Rate> {
@@ -170,6 +177,16 @@ mod tests {
         assert_eq!(rate.inflation(), 3.0);
     }
 
+    #[test]
+    fn cumulativeinflation() {
+        let rates = vec![Rate::new(1.0, 1.0, 2.0), Rate::new(1.0, 1.0, 0.5), Rate::new(1.0, 1.0, 4.0)];
+
+        assert_eq!(cumulative_inflation(&rates, Period::new(0)), 1.0);
+        assert_eq!(cumulative_inflation(&rates, Period::new(1)), 2.0);
+        assert_eq!(cumulative_inflation(&rates, Period::new(2)), 1.0);
+        assert_eq!(cumulative_inflation(&rates, Period::new(3)), 4.0);
+    }
+
     #[test]
     #[should_panic]
     fn rateprovider_sublength0() {
@@ -0,0 +1,168 @@
+use wasm_bindgen::prelude::*;
+
+use crate::montecarlo::Period;
+use crate::rates::Rate;
+use crate::simplifying_assumption;
+
+const EARLIEST_CLAIMING_AGE: usize = 62;
+const LATEST_CLAIMING_AGE: usize = 70;
+// Earnings before this age are indexed, later earnings count at their nominal value
+const INDEXING_AGE: usize = 60;
+const COMPUTATION_YEARS: usize = 35;
+
+// Inflation compounded between two periods, past the end of the rates if the person dies first
+fn inflation_between(rates: &[Rate], from: usize, to: usize) -> f64 {
+    rates[usize::min(from, rates.len())..usize::min(to, rates.len())].iter().map(|r| r.inflation()).product()
+}
+
+// Benefit formula parameters, with bend points in today's dollars
+simplifying_assumption!("Social Security uses simulated inflation in place of the national average \
+    wage index, and every worker is assumed to have the 40 credits needed to qualify.");
+#[derive(Clone,Debug)]
+#[wasm_bindgen]
+pub struct SocialSecuritySettings {
+    claiming_age_months: usize,
+    full_retirement_age_months: usize,
+    first_bend_point: f64,
+    second_bend_point: f64,
+    // Annual earnings from before the simulation starts, in today's dollars
+    past_earnings: Vec<f64>
+}
+
+#[wasm_bindgen]
+impl SocialSecuritySettings {
+    #[wasm_bindgen(constructor)]
+    pub fn new(claiming_age_months: usize, full_retirement_age_months: usize, first_bend_point: f64, second_bend_point: f64, past_earnings: Vec<f64>) -> SocialSecuritySettings {
+        assert!((EARLIEST_CLAIMING_AGE * 12..=LATEST_CLAIMING_AGE * 12).contains(&claiming_age_months));
+        assert!(first_bend_point <= second_bend_point);
+
+        SocialSecuritySettings { claiming_age_months, full_retirement_age_months, first_bend_point, second_bend_point, past_earnings }
+    }
+
+    // Bend points for 2023, for anyone born in 1960 or later
+    #[wasm_bindgen]
+    pub fn ssa_2023(claiming_age_months: usize, past_earnings: Vec<f64>) -> SocialSecuritySettings {
+        Self::new(claiming_age_months, 67 * 12, 1115.0, 6721.0, past_earnings)
+    }
+}
+
+impl SocialSecuritySettings {
+    pub fn with_claiming_age(&self, claiming_age_months: usize) -> SocialSecuritySettings {
+        Self::new(claiming_age_months, self.full_retirement_age_months, self.first_bend_point, self.second_bend_point, self.past_earnings.clone())
+    }
+
+    // Reduction for each month claimed before full retirement age, or delayed retirement credit for
+    // each month after
+    fn claiming_adjustment(&self) -> f64 {
+        if self.claiming_age_months < self.full_retirement_age_months {
+            let early = self.full_retirement_age_months - self.claiming_age_months;
+            let first_36 = usize::min(early, 36);
+            1.0 - first_36 as f64 * 5.0 / 900.0 - (early - first_36) as f64 * 5.0 / 1200.0
+        } else {
+            1.0 + (self.claiming_age_months - self.full_retirement_age_months) as f64 * 2.0 / 300.0
+        }
+    }
+
+    // Primary insurance amount for the given average indexed monthly earnings, with bend points
+    // scaled to the same dollars
+    fn primary_insurance_amount(&self, aime: f64, bend_point_scale: f64) -> f64 {
+        let first = self.first_bend_point * bend_point_scale;
+        let second = self.second_bend_point * bend_point_scale;
+
+        0.9 * f64::min(aime, first)
+            + 0.32 * (aime.clamp(first, second) - first)
+            + 0.15 * f64::max(aime - second, 0.0)
+    }
+}
+
+simplifying_assumption!("Benefits are only paid once retired, so there's no earnings test, and \
+    COLAs take effect at the start of each simulated year, going down with deflation.");
+#[derive(Clone,Debug)]
+pub struct SocialSecurity {
+    claiming_age_months: usize,
+    // Period the person turns 62, which COLAs are counted from
+    eligibility: usize,
+    // Monthly benefit as of the eligibility period, after the claiming adjustment
+    monthly_benefit: f64
+}
+
+impl SocialSecurity {
+    // covered_earnings is the monthly gross pay from the start of the simulation
+    pub fn new(settings: &SocialSecuritySettings, covered_earnings: &[f64], starting_age_months: usize, rates: &[Rate]) -> SocialSecurity {
+        let period_at_age = |age: usize| (age * 12).saturating_sub(starting_age_months);
+        let indexing = period_at_age(INDEXING_AGE);
+
+        let mut annual_earnings: Vec<f64> = settings.past_earnings.iter()
+            .map(|earnings| earnings * inflation_between(rates, 0, indexing))
+            .collect();
+        annual_earnings.extend(covered_earnings.chunks(12).enumerate().map(|(year, earnings)| {
+            let start = year * 12;
+            let indexed = if start < indexing { inflation_between(rates, start, indexing) } else { 1.0 };
+            earnings.iter().sum::<f64>() * indexed
+        }));
+
+        annual_earnings.sort_by(|a, b| b.total_cmp(a));
+        let aime = annual_earnings.iter().take(COMPUTATION_YEARS).sum::<f64>() / (COMPUTATION_YEARS * 12) as f64;
+        let pia = settings.primary_insurance_amount(aime, inflation_between(rates, 0, indexing));
+
+        SocialSecurity {
+            claiming_age_months: settings.claiming_age_months,
+            eligibility: period_at_age(EARLIEST_CLAIMING_AGE),
+            monthly_benefit: pia * settings.claiming_adjustment()
+        }
+    }
+
+    pub fn monthly_benefit(&self, period: Period, age_months: usize, rates: &[Rate]) -> f64 {
+        if age_months < self.claiming_age_months {
+            return 0.0;
+        }
+
+        let last_cola = usize::max(period.round_down_to_year().get(), self.eligibility);
+        self.monthly_benefit * inflation_between(rates, self.eligibility, last_cola)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_float_eq::*;
+
+    use super::*;
+
+    #[test]
+    pub fn claimingadjustment() {
+        let settings = |claiming_age| SocialSecuritySettings::new(claiming_age, 67 * 12, 1000.0, 6000.0, vec![]);
+
+        assert_float_absolute_eq!(settings(62 * 12).claiming_adjustment(), 0.7);
+        assert_float_absolute_eq!(settings(64 * 12).claiming_adjustment(), 0.8);
+        assert_float_absolute_eq!(settings(67 * 12).claiming_adjustment(), 1.0);
+        assert_float_absolute_eq!(settings(70 * 12).claiming_adjustment(), 1.24);
+    }
+
+    #[test]
+    pub fn primaryinsuranceamount() {
+        let settings = SocialSecuritySettings::new(67 * 12, 67 * 12, 1000.0, 6000.0, vec![]);
+
+        assert_float_absolute_eq!(settings.primary_insurance_amount(500.0, 1.0), 450.0);
+        assert_float_absolute_eq!(settings.primary_insurance_amount(3000.0, 1.0), 1540.0);
+        assert_float_absolute_eq!(settings.primary_insurance_amount(8000.0, 1.0), 2800.0);
+        assert_float_absolute_eq!(settings.primary_insurance_amount(6000.0, 2.0), 3080.0);
+    }
+
+    #[test]
+    pub fn socialsecurity_monthlybenefit() {
+        // Prices double in the first month, and go up 10% the year after turning 62
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 120];
+        rates[0] = Rate::new(1.0, 1.0, 2.0);
+        rates[40] = Rate::new(1.0, 1.0, 1.1);
+
+        // 33 years of 12000 in today's dollars, and two simulated years of 12000 nominal, starting at 59
+        let settings = SocialSecuritySettings::new(67 * 12, 67 * 12, 1000.0, 6000.0, vec![12000.0; 33]);
+        let ss = SocialSecurity::new(&settings, &[1000.0; 24], 59 * 12, &rates);
+
+        // Only earnings before 60 are indexed, so the year at 60 is worth half as much:
+        // (34 * 24000 + 12000) / 420, with the first bend point at 2000
+        let aime = (34.0 * 24000.0 + 12000.0) / 420.0;
+        assert_eq!(ss.monthly_benefit(Period::new(95), 66 * 12 + 11, &rates), 0.0);
+        assert_float_absolute_eq!(ss.monthly_benefit(Period::new(96), 67 * 12, &rates), 0.9 * aime * 1.1);
+    }
+}
@@ -0,0 +1,261 @@
+use wasm_bindgen::prelude::*;
+
+use crate::montecarlo::Period;
+use crate::rates::{Rate, cumulative_inflation};
+use crate::simplifying_assumption;
+
+// Multiplies a spending line from the given age on, until the next multiplier's age
+#[derive(Copy,Clone,Debug)]
+pub struct SpendingMultiplier {
+    pub from_age: usize,
+    pub multiplier: f64
+}
+
+// Annual spending in today's dollars, scaled by age
+#[derive(Clone,Debug,Default)]
+pub struct SpendingLine {
+    annual: f64,
+    multipliers: Vec<SpendingMultiplier>
+}
+
+impl SpendingLine {
+    pub fn new(annual: f64, mut multipliers: Vec<SpendingMultiplier>) -> SpendingLine {
+        multipliers.sort_by_key(|m| m.from_age);
+
+        SpendingLine { annual, multipliers }
+    }
+
+    pub fn flat(annual: f64) -> SpendingLine {
+        Self::new(annual, vec![])
+    }
+
+    fn new_from_js(annual: f64, ages: Vec<usize>, multipliers: Vec<f64>) -> SpendingLine {
+        assert_eq!(ages.len(), multipliers.len());
+
+        let multipliers = ages.into_iter().zip(multipliers)
+            .map(|(from_age, multiplier)| SpendingMultiplier { from_age, multiplier })
+            .collect();
+
+        Self::new(annual, multipliers)
+    }
+
+    fn multiplier(&self, age_years: usize) -> f64 {
+        self.multipliers.iter().rev()
+            .find(|m| m.from_age <= age_years)
+            .map_or(1.0, |m| m.multiplier)
+    }
+
+    // Nominal spending for the month, given inflation since today
+    pub fn monthly(&self, age_months: usize, cumulative_inflation: f64) -> f64 {
+        self.annual * self.multiplier(age_months / 12) * cumulative_inflation / 12.0
+    }
+}
+
+#[derive(Copy,Clone,Debug,Default,PartialEq)]
+pub struct MonthlySpending {
+    pub essential: f64,
+    pub discretionary: f64
+}
+
+impl MonthlySpending {
+    pub fn total(&self) -> f64 {
+        self.essential + self.discretionary
+    }
+}
+
+#[derive(Copy,Clone,Debug,PartialEq,Eq)]
+#[wasm_bindgen]
+pub enum SpendingPriority {
+    Essential,
+    Discretionary
+}
+
+#[derive(Copy,Clone,Debug)]
+pub enum ExpenseInflation {
+    // Grows with simulated inflation
+    Cpi,
+    // A fixed nominal amount, e.g. a known balloon payment
+    Nominal,
+    // Grows at its own annual rate, e.g. 0.05 for college tuition
+    Fixed(f64)
+}
+
+// A one-off or recurring lump sum, in today's dollars, paid on the birthday it comes due
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct ScheduledExpense {
+    amount: f64,
+    start_age: usize,
+    // 0 for a one-off expense
+    every_years: usize,
+    end_age: usize,
+    inflation: ExpenseInflation,
+    priority: SpendingPriority
+}
+
+impl ScheduledExpense {
+    pub fn new(amount: f64, start_age: usize, every_years: usize, end_age: usize, inflation: ExpenseInflation, priority: SpendingPriority) -> ScheduledExpense {
+        assert!(start_age <= end_age);
+
+        ScheduledExpense { amount, start_age, every_years, end_age, inflation, priority }
+    }
+
+    pub fn priority(&self) -> SpendingPriority {
+        self.priority
+    }
+
+    fn is_due(&self, age_months: usize) -> bool {
+        if !age_months.is_multiple_of(12) {
+            return false;
+        }
+
+        let age = age_months / 12;
+        if age < self.start_age || age > self.end_age {
+            false
+        } else if self.every_years == 0 {
+            age == self.start_age
+        } else {
+            (age - self.start_age).is_multiple_of(self.every_years)
+        }
+    }
+
+    // The nominal amount due in the period, or 0 if nothing is due
+    pub fn amount_due(&self, age_months: usize, rates: &[Rate], period: Period) -> f64 {
+        if !self.is_due(age_months) {
+            return 0.0;
+        }
+
+        let inflation = match self.inflation {
+            ExpenseInflation::Cpi => cumulative_inflation(rates, period),
+            ExpenseInflation::Nominal => 1.0,
+            ExpenseInflation::Fixed(rate) => (1.0 + rate).powf(period.get() as f64 / 12.0)
+        };
+
+        self.amount * inflation
+    }
+}
+
+#[wasm_bindgen]
+impl ScheduledExpense {
+    #[wasm_bindgen]
+    pub fn once(amount: f64, age: usize, priority: SpendingPriority) -> ScheduledExpense {
+        Self::new(amount, age, 0, age, ExpenseInflation::Cpi, priority)
+    }
+
+    #[wasm_bindgen]
+    pub fn recurring(amount: f64, start_age: usize, every_years: usize, end_age: usize, priority: SpendingPriority) -> ScheduledExpense {
+        Self::new(amount, start_age, every_years, end_age, ExpenseInflation::Cpi, priority)
+    }
+
+    #[wasm_bindgen]
+    pub fn with_nominal_amount(self) -> ScheduledExpense {
+        ScheduledExpense { inflation: ExpenseInflation::Nominal, ..self }
+    }
+
+    #[wasm_bindgen]
+    pub fn with_inflation_rate(self, annual_rate: f64) -> ScheduledExpense {
+        ScheduledExpense { inflation: ExpenseInflation::Fixed(annual_rate), ..self }
+    }
+}
+
+// Retirement spending that doesn't depend on pre-retirement income, split into what has to be
+// spent and what can be cut.  Age multipliers can express the "go-go, slow-go, no-go" spending
+// smile, e.g. discretionary spending of 1.0 until 75, 0.8 until 85 and 0.6 after that.
+simplifying_assumption!("Spending multipliers change in steps at whole ages instead of gradually.");
+#[derive(Clone,Debug,Default)]
+#[wasm_bindgen]
+pub struct SpendingPlan {
+    essential: SpendingLine,
+    discretionary: SpendingLine,
+    // Paid before retirement as well as after
+    expenses: Vec<ScheduledExpense>
+}
+
+impl SpendingPlan {
+    pub fn new(essential: SpendingLine, discretionary: SpendingLine) -> SpendingPlan {
+        SpendingPlan { essential, discretionary, expenses: vec![] }
+    }
+
+    pub fn expenses(&self) -> &[ScheduledExpense] {
+        &self.expenses
+    }
+
+    // The same real spending every year, all of it essential
+    pub fn flat(annual: f64) -> SpendingPlan {
+        Self::new(SpendingLine::flat(annual), SpendingLine::default())
+    }
+
+    pub fn monthly_spending(&self, age_months: usize, cumulative_inflation: f64) -> MonthlySpending {
+        MonthlySpending {
+            essential: self.essential.monthly(age_months, cumulative_inflation),
+            discretionary: self.discretionary.monthly(age_months, cumulative_inflation)
+        }
+    }
+}
+
+#[wasm_bindgen]
+impl SpendingPlan {
+    #[wasm_bindgen(constructor)]
+    pub fn new_from_js(essential_annual: f64, essential_ages: Vec<usize>, essential_multipliers: Vec<f64>, discretionary_annual: f64, discretionary_ages: Vec<usize>, discretionary_multipliers: Vec<f64>) -> SpendingPlan {
+        Self::new(
+            SpendingLine::new_from_js(essential_annual, essential_ages, essential_multipliers),
+            SpendingLine::new_from_js(discretionary_annual, discretionary_ages, discretionary_multipliers)
+        )
+    }
+
+    #[wasm_bindgen]
+    pub fn add_expense(&mut self, expense: ScheduledExpense) {
+        self.expenses.push(expense);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_float_eq::*;
+
+    use super::*;
+
+    #[test]
+    pub fn spendingline_multiplier() {
+        let line = SpendingLine::new(12000.0, vec![SpendingMultiplier { from_age: 85, multiplier: 0.6 }, SpendingMultiplier { from_age: 75, multiplier: 0.8 }]);
+
+        assert_eq!(line.multiplier(65), 1.0);
+        assert_eq!(line.multiplier(75), 0.8);
+        assert_eq!(line.multiplier(84), 0.8);
+        assert_eq!(line.multiplier(90), 0.6);
+    }
+
+    #[test]
+    pub fn scheduledexpense_amountdue() {
+        let rates = vec![Rate::new(1.0, 1.0, 1.5); 24];
+        let car = ScheduledExpense::recurring(30000.0, 60, 10, 80, SpendingPriority::Discretionary);
+
+        assert_eq!(car.amount_due(59 * 12, &rates, Period::new(0)), 0.0);
+        assert_eq!(car.amount_due(60 * 12, &rates, Period::new(0)), 30000.0);
+        assert_eq!(car.amount_due(60 * 12 + 1, &rates, Period::new(1)), 0.0);
+        assert_eq!(car.amount_due(65 * 12, &rates, Period::new(1)), 0.0);
+        assert_eq!(car.amount_due(70 * 12, &rates, Period::new(1)), 45000.0);
+        assert_eq!(car.amount_due(90 * 12, &rates, Period::new(1)), 0.0);
+
+        let wedding = ScheduledExpense::once(20000.0, 62, SpendingPriority::Discretionary).with_nominal_amount();
+        assert_eq!(wedding.amount_due(62 * 12, &rates, Period::new(2)), 20000.0);
+        assert_eq!(wedding.amount_due(63 * 12, &rates, Period::new(2)), 0.0);
+
+        let tuition = ScheduledExpense::recurring(10000.0, 50, 1, 53, SpendingPriority::Essential).with_inflation_rate(0.05);
+        assert_float_absolute_eq!(tuition.amount_due(52 * 12, &rates, Period::new(24)), 11025.0);
+    }
+
+    #[test]
+    pub fn spendingplan_monthlyspending() {
+        let plan = SpendingPlan::new_from_js(24000.0, vec![80], vec![1.5], 12000.0, vec![75], vec![0.5]);
+
+        let spending = plan.monthly_spending(70 * 12, 1.0);
+        assert_eq!(spending, MonthlySpending { essential: 2000.0, discretionary: 1000.0 });
+
+        // Inflation applies to both lines, on top of the multipliers
+        let spending = plan.monthly_spending(80 * 12 + 6, 2.0);
+        assert_float_absolute_eq!(spending.essential, 6000.0);
+        assert_float_absolute_eq!(spending.discretionary, 1000.0);
+        assert_float_absolute_eq!(spending.total(), 7000.0);
+    }
+}
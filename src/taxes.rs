@@ -67,8 +67,14 @@ impl TaxSettings {
 
 #[cfg_attr(test, automock)]
 pub trait TaxCollector {
-    fn new(settings: TaxSettings, rates: Rc<Vec<Rate>>, lifespan: Lifespan) -> Self;
+    fn new(settings: TaxSettings, rates: Rc<Vec<Rate>>, lifespan: Lifespan) -> Self where Self: Sized;
     fn collect_income_taxes(&mut self, money: Money, period: Period) -> TaxResult;
+    // The taxes collect_income_taxes would charge, without actually collecting them
+    fn estimate_income_taxes(&self, money: &Money, period: Period) -> f64;
+    // Taxable income collected so far in the calendar year containing the period
+    fn income_this_year(&self, period: Period) -> f64;
+    // Everything collected over the lifespan so far
+    fn total_taxes(&self) -> f64;
 }
 
 #[derive(Debug)]
@@ -76,7 +82,8 @@ pub trait TaxCollector {
 pub struct Tax {
     settings: TaxSettings,
     rates: Rc<Vec<Rate>>,
-    gross_income: Vec<f64>
+    gross_income: Vec<f64>,
+    taxes_paid: Vec<f64>
 }
 
 impl Tax {
@@ -123,30 +130,48 @@ impl Tax {
     pub fn new(settings: TaxSettings, rates: Rc<Vec<Rate>>, lifespan: Lifespan) -> Tax {
         assert_eq!(rates.len(), lifespan.periods());
 
-        Tax{ settings, rates, gross_income: vec![0.0; lifespan.periods()] }
+        Tax{ settings, rates, gross_income: vec![0.0; lifespan.periods()], taxes_paid: vec![0.0; lifespan.periods()] }
     }
 }
 
 impl Tax {
     pub fn collect_income_taxes(&mut self, money: Money, period: Period) -> TaxResult {
+        let taxes = self.estimate_income_taxes(&money, period);
+
         match money {
             Money::NonTaxable(amt) => {
-                TaxResult{taxes: 0.0, leftover: amt}
+                TaxResult{taxes, leftover: amt}
             },
             Money::Taxable(amt) => {
-                let year_begin = period.round_down_to_year();
-                let cumulative_annual_gross_income: f64 = self.gross_income[year_begin.get()..=period.get()].iter().sum();
-                let taxes_paid = self.calculate_tax_amount(cumulative_annual_gross_income, period);
                 self.gross_income[period.get()] += amt;
+                self.taxes_paid[period.get()] += taxes;
+                
+                TaxResult{taxes, leftover: amt - taxes}
+            }
+        }
+    }
+
+    pub fn estimate_income_taxes(&self, money: &Money, period: Period) -> f64 {
+        match money {
+            Money::NonTaxable(_) => 0.0,
+            Money::Taxable(amt) => {
+                let cumulative_annual_gross_income = self.income_this_year(period);
+                let taxes_paid = self.calculate_tax_amount(cumulative_annual_gross_income, period);
                 let total_taxes = self.calculate_tax_amount(cumulative_annual_gross_income + amt, period);
 
-                let taxes = total_taxes - taxes_paid;
-                let leftover = amt - taxes;
-                
-                TaxResult{taxes, leftover}
+                total_taxes - taxes_paid
             }
         }
     }
+
+    pub fn income_this_year(&self, period: Period) -> f64 {
+        let year_begin = period.round_down_to_year();
+        self.gross_income[year_begin.get()..=period.get()].iter().sum()
+    }
+
+    pub fn total_taxes(&self) -> f64 {
+        self.taxes_paid.iter().sum()
+    }
 }
 
 impl TaxCollector for Tax {
@@ -157,6 +182,18 @@ impl TaxCollector for Tax {
     fn collect_income_taxes(&mut self, money: Money, period: Period) -> TaxResult {
         self.collect_income_taxes(money, period)
     }
+
+    fn estimate_income_taxes(&self, money: &Money, period: Period) -> f64 {
+        self.estimate_income_taxes(money, period)
+    }
+
+    fn income_this_year(&self, period: Period) -> f64 {
+        self.income_this_year(period)
+    }
+
+    fn total_taxes(&self) -> f64 {
+        self.total_taxes()
+    }
 }
 
 #[cfg(test)]
@@ -446,4 +483,34 @@ mod tests {
         assert_float_absolute_eq!(ret.taxes(), 120.0);
         assert_float_absolute_eq!(ret.leftover(), 880.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn estimateincometaxes_doesntcollect() {
+        let lifespan = Lifespan::new(12);
+        let brackets = vec![TaxBracket { floor: 0.0, rate: 0.1 }, TaxBracket { floor: 1000.0, rate: 0.2 } ];
+        let settings = TaxSettings { deduction: 0.0, adjust_deduction_for_inflation: false, brackets, adjust_bracket_floors_for_inflation: false };
+        let mut tax = Tax::new(settings, Rc::new(vec![Rate::new(1.0, 1.0, 1.0); 12]), lifespan);
+
+        assert_float_absolute_eq!(tax.estimate_income_taxes(&Money::Taxable(1500.0), Period::new(0)), 200.0);
+        assert_float_absolute_eq!(tax.estimate_income_taxes(&Money::Taxable(1500.0), Period::new(0)), 200.0);
+        assert_float_absolute_eq!(tax.estimate_income_taxes(&Money::NonTaxable(1500.0), Period::new(0)), 0.0);
+
+        tax.collect_income_taxes(Money::Taxable(1000.0), Period::new(0));
+        assert_float_absolute_eq!(tax.estimate_income_taxes(&Money::Taxable(500.0), Period::new(1)), 100.0);
+    }
+
+    #[test]
+    pub fn totaltaxes_multiyear() {
+        let lifespan = Lifespan::new(24);
+        let brackets = vec![TaxBracket { floor: 0.0, rate: 0.1 }, TaxBracket { floor: 1000.0, rate: 0.2 } ];
+        let settings = TaxSettings { deduction: 0.0, adjust_deduction_for_inflation: false, brackets, adjust_bracket_floors_for_inflation: false };
+        let mut tax = Tax::new(settings, Rc::new(vec![Rate::new(1.0, 1.0, 1.0); 24]), lifespan);
+
+        tax.collect_income_taxes(Money::Taxable(1500.0), Period::new(0));
+        tax.collect_income_taxes(Money::NonTaxable(1500.0), Period::new(1));
+        tax.estimate_income_taxes(&Money::Taxable(1500.0), Period::new(2));
+        tax.collect_income_taxes(Money::Taxable(500.0), Period::new(12));
+
+        assert_float_absolute_eq!(tax.total_taxes(), 250.0);
+    }
+}
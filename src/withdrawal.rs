@@ -1,35 +1,1135 @@
-use crate::assets::Account;
+use wasm_bindgen::prelude::*;
+
+use crate::assets::{Account, AccountTaxTreatment, BondLadder};
 use crate::montecarlo::Period;
+use crate::rates::{Rate, cumulative_inflation};
+use crate::simplifying_assumption;
+use crate::socialsecurity::SocialSecuritySettings;
+use crate::spending::{MonthlySpending, SpendingPlan};
+use crate::taxes::{Money, TaxCollector};
+
+// Everything a strategy gets to look at when deciding how much to withdraw, built by
+// Run::populate every period of retirement
+pub struct WithdrawalContext<'a> {
+    pub period: Period,
+    pub retirement: Period,
+    pub age_months: usize,
+    pub remaining_life_expectancy: f64,
+    pub rates: &'a [Rate],
+    // Portfolio balance carried into each period, from the start of the simulation through this one
+    pub portfolio_history: &'a [f64],
+    // Total withdrawn in each period of retirement before this one
+    pub past_withdrawals: &'a [f64],
+    // For estimating the taxes on a withdrawal, given the income taxed so far this year
+    pub tax: &'a dyn TaxCollector,
+    pub spending_plan: &'a SpendingPlan,
+    // Social Security and pensions paid this period, after taxes
+    pub guaranteed_income: f64,
+    // Tax-aware order to take withdrawals in, filled in by OrderedWithdrawal
+    pub order: Option<WithdrawalOrder>
+}
+
+impl WithdrawalContext<'_> {
+    pub fn portfolio(&self) -> f64 {
+        self.portfolio_history[self.period.get()]
+    }
+
+    // Inflation from the beginning of the simulation
+    pub fn cumulative_inflation(&self) -> f64 {
+        cumulative_inflation(self.rates, self.period)
+    }
+
+    pub fn inflation_since(&self, period: Period) -> f64 {
+        self.cumulative_inflation() / cumulative_inflation(self.rates, period)
+    }
+
+    pub fn is_retirement(&self) -> bool {
+        self.period.get() == self.retirement.get()
+    }
+
+    pub fn years_retired(&self) -> usize {
+        (self.period.get() - self.retirement.get()) / 12
+    }
+
+    // The first period of each year of retirement after the first
+    pub fn is_anniversary(&self) -> bool {
+        !self.is_retirement() && (self.period - self.retirement.get()).is_new_year()
+    }
+
+    pub fn last_year_rates(&self) -> &[Rate] {
+        &self.rates[(self.period - 12).get()..self.period.get()]
+    }
+
+    pub fn last_year_withdrawals(&self) -> f64 {
+        self.past_withdrawals[self.past_withdrawals.len() - 12..].iter().sum()
+    }
+
+    // What the spending plan calls for this month, in nominal dollars
+    pub fn planned_spending(&self) -> MonthlySpending {
+        self.spending_plan.monthly_spending(self.age_months, self.cumulative_inflation())
+    }
+}
+
+// Shortfalls under a cent are rounding error from splitting withdrawals across accounts
+pub const SHORTFALL_TOLERANCE: f64 = 0.01;
+
+pub struct WithdrawalResult {
+    // What was actually taken out of each account
+    pub withdrawals: Vec<f64>,
+    // How much of the intended withdrawal couldn't be covered
+    pub shortfall: f64
+}
+
+impl WithdrawalResult {
+    pub fn total(&self) -> f64 {
+        self.withdrawals.iter().sum()
+    }
+
+    pub fn is_shortfall(&self) -> bool {
+        self.shortfall >= SHORTFALL_TOLERANCE
+    }
+}
 
+// Strategies are created per run at retirement, so they can keep track of their own state
 pub trait WithdrawalStrategy {
-    fn execute(&self, withdrawal: f64, accounts: &mut Vec<Account>, period: Period) -> Result<(), f64>;
+    // How much to spend this period, counting guaranteed income, which is taken out before
+    // withdrawing the rest.  Called after the accounts have been invested for the period, but
+    // before anything is withdrawn.
+    fn withdrawal_amount(&mut self, accounts: &[Account], context: &WithdrawalContext) -> f64;
+
+    // Takes the withdrawal out of the accounts
+    fn withdraw(&mut self, withdrawal: f64, accounts: &mut [Account], context: &WithdrawalContext) -> WithdrawalResult {
+        withdraw_from_accounts(withdrawal, accounts, context)
+    }
+
+    fn execute(&mut self, accounts: &mut [Account], context: &WithdrawalContext) -> WithdrawalResult {
+        let withdrawal = self.withdrawal_amount(accounts, context);
+        self.withdraw(withdrawal, accounts, context)
+    }
 }
 
-pub struct WithdrawalStrategyOrig {
+#[derive(Copy,Clone,Debug)]
+pub enum ConstantDollarAmount {
+    // Annual withdrawal as a percentage of the portfolio at retirement, e.g. 0.04
+    InitialRate(f64),
+    // Annual spending in today's dollars
+    Target(f64)
+}
+
+// Guyton-Klinger decision rules, checked on each anniversary of retirement.  Thresholds are
+// relative to the initial withdrawal rate, e.g. a 0.2 capital preservation threshold with a 5%
+// initial rate cuts spending when the current rate goes above 6%.
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct GuytonKlingerSettings {
+    pub initial_rate: f64,
+    pub capital_preservation_threshold: f64,
+    pub capital_preservation_cut: f64,
+    // The capital preservation rule stops applying this many years into retirement
+    pub capital_preservation_years: usize,
+    pub prosperity_threshold: f64,
+    pub prosperity_raise: f64,
+    // After a year where stocks lost money, withdraw from the most bond-heavy accounts first
+    pub portfolio_management: bool
+}
+
+#[wasm_bindgen]
+impl GuytonKlingerSettings {
+    #[wasm_bindgen(constructor)]
+    pub fn new(initial_rate: f64, capital_preservation_threshold: f64, capital_preservation_cut: f64, capital_preservation_years: usize, prosperity_threshold: f64, prosperity_raise: f64, portfolio_management: bool) -> GuytonKlingerSettings {
+        GuytonKlingerSettings { initial_rate, capital_preservation_threshold, capital_preservation_cut, capital_preservation_years, prosperity_threshold, prosperity_raise, portfolio_management }
+    }
+
+    // The rules as published, for a 30-year retirement
+    #[wasm_bindgen]
+    pub fn published(initial_rate: f64) -> GuytonKlingerSettings {
+        Self::new(initial_rate, 0.2, 0.1, 15, 0.2, 0.1, true)
+    }
+}
+
+#[derive(Copy,Clone,Debug)]
+pub enum VpwHorizon {
+    // Plan to spend everything by this age
+    EndAge(usize),
+    // Plan over the remaining life expectancy from the mortality table, recalculated every year
+    LifeExpectancy
+}
+
+// Variable percentage withdrawal: each year, spend the payment that would amortize the portfolio
+// over the remaining horizon at its expected real return
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct VpwSettings {
+    stocks_real_return: f64,
+    bonds_real_return: f64,
+    horizon: VpwHorizon,
+    // Expected annual real growth in spending.  Negative values front-load spending.
+    tilt: f64
+}
+
+#[wasm_bindgen]
+impl VpwSettings {
+    #[wasm_bindgen]
+    pub fn new_with_end_age(stocks_real_return: f64, bonds_real_return: f64, end_age: usize, tilt: f64) -> VpwSettings {
+        Self::new(stocks_real_return, bonds_real_return, VpwHorizon::EndAge(end_age), tilt)
+    }
+
+    #[wasm_bindgen]
+    pub fn new_with_life_expectancy(stocks_real_return: f64, bonds_real_return: f64, tilt: f64) -> VpwSettings {
+        Self::new(stocks_real_return, bonds_real_return, VpwHorizon::LifeExpectancy, tilt)
+    }
+}
+
+impl VpwSettings {
+    pub fn new(stocks_real_return: f64, bonds_real_return: f64, horizon: VpwHorizon, tilt: f64) -> VpwSettings {
+        VpwSettings { stocks_real_return, bonds_real_return, horizon, tilt }
+    }
+}
+
+// Spend a fixed percentage of the portfolio each year, but limit the change in real spending from
+// the previous year to between floor and ceiling, e.g. -0.025 and 0.05
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct VanguardDynamicSettings {
+    pub rate: f64,
+    pub ceiling: f64,
+    pub floor: f64
+}
+
+#[wasm_bindgen]
+impl VanguardDynamicSettings {
+    #[wasm_bindgen(constructor)]
+    pub fn new(rate: f64, ceiling: f64, floor: f64) -> VanguardDynamicSettings {
+        assert!(floor <= ceiling);
+
+        VanguardDynamicSettings { rate, ceiling, floor }
+    }
+}
+
+// Constant-dollar spending from an initial withdrawal rate, drawn from a cash bucket holding
+// cash_years of spending and refilled on each anniversary of retirement, with bond_years of spending
+// in a bond bucket and the rest in stocks
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct BucketSettings {
+    pub initial_rate: f64,
+    pub cash_years: f64,
+    pub bond_years: f64,
+    // Only sell stocks to refill the other buckets after a year stocks went up.  Otherwise stocks
+    // refill them every year.
+    pub refill_only_after_gains: bool
+}
+
+#[wasm_bindgen]
+impl BucketSettings {
+    #[wasm_bindgen(constructor)]
+    pub fn new(initial_rate: f64, cash_years: f64, bond_years: f64, refill_only_after_gains: bool) -> BucketSettings {
+        BucketSettings { initial_rate, cash_years, bond_years, refill_only_after_gains }
+    }
+}
+
+// Cuts discretionary spending while the planned spending is more than max_withdrawal_rate of the
+// portfolio, e.g. by 0.5 above a 6% withdrawal rate
+#[derive(Copy,Clone,Debug)]
+pub struct DiscretionaryCut {
+    pub max_withdrawal_rate: f64,
+    pub cut: f64
+}
+
+impl DiscretionaryCut {
+    pub fn none() -> DiscretionaryCut {
+        DiscretionaryCut { max_withdrawal_rate: f64::INFINITY, cut: 0.0 }
+    }
+}
+
+// Essential spending is funded by a bond ladder bought at retirement, covering ladder_years, and
+// discretionary spending by whatever is left in the accounts.  The yield curve is annual yields by
+// years to maturity, real for a TIPS ladder and nominal otherwise.
+#[derive(Clone,Debug)]
+#[wasm_bindgen]
+pub struct FloorAndUpsideSettings {
+    ladder_years: usize,
+    yield_curve: Vec<f64>,
+    inflation_indexed: bool
+}
+
+#[wasm_bindgen]
+impl FloorAndUpsideSettings {
+    #[wasm_bindgen(constructor)]
+    pub fn new(ladder_years: usize, yield_curve: Vec<f64>, inflation_indexed: bool) -> FloorAndUpsideSettings {
+        assert!(!yield_curve.is_empty());
+
+        FloorAndUpsideSettings { ladder_years, yield_curve, inflation_indexed }
+    }
+}
+
+#[derive(Clone,Debug)]
+pub enum WithdrawalRule {
+    // Withdraw the nominal pre-retirement take-home pay every month
+    Orig,
+    // Withdraw whatever the spending plan calls for every month
+    SpendingPlan(DiscretionaryCut),
+    ConstantDollar{amount: ConstantDollarAmount},
+    GuytonKlinger(GuytonKlingerSettings),
+    Vpw(VpwSettings),
+    VanguardDynamic(VanguardDynamicSettings),
+    Bucket(BucketSettings),
+    FloorAndUpside(FloorAndUpsideSettings)
+}
+
+// Which accounts to take each withdrawal from
+#[derive(Copy,Clone,Debug)]
+pub enum WithdrawalOrder {
+    // Pro rata across all accounts by balance
+    Proportional,
+    // Taxable accounts, then tax-deferred, then tax-free
+    TaxableFirst,
+    // Tax-deferred accounts until taxable income for the year reaches the ceiling, e.g. the top of
+    // the 12% bracket plus the deduction, then taxable, then tax-free, then the rest of tax-deferred.
+    // The ceiling is annual income in today's dollars.
+    BracketFilling{income_ceiling: f64}
+}
+
+#[wasm_bindgen]
+pub struct WithdrawalOrderJS {
+    order: WithdrawalOrder
+}
+
+#[wasm_bindgen]
+impl WithdrawalOrderJS {
+    #[wasm_bindgen]
+    pub fn proportional() -> WithdrawalOrderJS {
+        WithdrawalOrderJS { order: WithdrawalOrder::Proportional }
+    }
+
+    #[wasm_bindgen]
+    pub fn taxable_first() -> WithdrawalOrderJS {
+        WithdrawalOrderJS { order: WithdrawalOrder::TaxableFirst }
+    }
+
+    #[wasm_bindgen]
+    pub fn bracket_filling(income_ceiling: f64) -> WithdrawalOrderJS {
+        WithdrawalOrderJS { order: WithdrawalOrder::BracketFilling { income_ceiling } }
+    }
+}
+
+// Without an order, the rule decides which accounts to withdraw from and withdrawals aren't grossed
+// up for taxes.  With one, the rule's withdrawal amount is net spending and enough is withdrawn to
+// also cover the taxes on it.
+#[derive(Clone,Debug)]
+pub struct WithdrawalSettings {
+    pub rule: WithdrawalRule,
+    pub order: Option<WithdrawalOrder>,
+    // Age when required minimum distributions from tax-deferred accounts start, e.g. 73 or 75
+    pub rmd_start_age: Option<usize>,
+    pub roth_conversions: Option<RothConversionSettings>,
+    // Without a plan, retirement spending is the pre-retirement take-home pay in real terms
+    pub spending_plan: Option<SpendingPlan>,
+    // Benefits are spent before anything is withdrawn
+    pub social_security: Option<SocialSecuritySettings>
+}
+
+impl From<WithdrawalRule> for WithdrawalSettings {
+    fn from(rule: WithdrawalRule) -> WithdrawalSettings {
+        WithdrawalSettings { rule, order: None, rmd_start_age: None, roth_conversions: None, spending_plan: None, social_security: None }
+    }
+}
+
+// How much to convert from tax-deferred to tax-free accounts each year, in today's dollars
+#[derive(Copy,Clone,Debug)]
+pub enum RothConversionAmount {
+    // Enough to bring the year's taxable income up to the ceiling, e.g. the top of the 22% bracket
+    // plus the deduction
+    FillTo{income_ceiling: f64},
+    Fixed{annual_amount: f64}
+}
+
+// Roth conversions at the end of each year from retirement until the end age
+#[derive(Copy,Clone,Debug)]
+#[wasm_bindgen]
+pub struct RothConversionSettings {
+    amount: RothConversionAmount,
+    end_age: usize
+}
+
+#[wasm_bindgen]
+impl RothConversionSettings {
+    #[wasm_bindgen]
+    pub fn fill_to(income_ceiling: f64, end_age: usize) -> RothConversionSettings {
+        Self::new(RothConversionAmount::FillTo { income_ceiling }, end_age)
+    }
+
+    #[wasm_bindgen]
+    pub fn fixed(annual_amount: f64, end_age: usize) -> RothConversionSettings {
+        Self::new(RothConversionAmount::Fixed { annual_amount }, end_age)
+    }
+}
+
+impl RothConversionSettings {
+    pub fn new(amount: RothConversionAmount, end_age: usize) -> RothConversionSettings {
+        RothConversionSettings { amount, end_age }
+    }
+}
+
+#[wasm_bindgen]
+pub struct WithdrawalSettingsJS {
+    withdrawal_settings: WithdrawalSettings
+}
+
+#[wasm_bindgen]
+impl WithdrawalSettingsJS {
+    #[wasm_bindgen]
+    pub fn orig() -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalRule::Orig.into() }
+    }
+
+    #[wasm_bindgen]
+    pub fn constant_dollar_rate(initial_rate: f64) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalRule::ConstantDollar { amount: ConstantDollarAmount::InitialRate(initial_rate) }.into() }
+    }
+
+    #[wasm_bindgen]
+    pub fn constant_dollar_target(annual_spending: f64) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalRule::ConstantDollar { amount: ConstantDollarAmount::Target(annual_spending) }.into() }
+    }
+
+    #[wasm_bindgen]
+    pub fn guyton_klinger(settings: GuytonKlingerSettings) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalRule::GuytonKlinger(settings).into() }
+    }
+
+    #[wasm_bindgen]
+    pub fn vpw(settings: VpwSettings) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalRule::Vpw(settings).into() }
+    }
+
+    #[wasm_bindgen]
+    pub fn vanguard_dynamic(settings: VanguardDynamicSettings) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalRule::VanguardDynamic(settings).into() }
+    }
+
+    #[wasm_bindgen]
+    pub fn bucket(settings: BucketSettings) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalRule::Bucket(settings).into() }
+    }
+
+    #[wasm_bindgen]
+    pub fn floor_and_upside(settings: FloorAndUpsideSettings) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalRule::FloorAndUpside(settings).into() }
+    }
+
+    #[wasm_bindgen]
+    pub fn spending_plan() -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalRule::SpendingPlan(DiscretionaryCut::none()).into() }
+    }
+
+    #[wasm_bindgen]
+    pub fn spending_plan_with_cuts(max_withdrawal_rate: f64, cut: f64) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalRule::SpendingPlan(DiscretionaryCut { max_withdrawal_rate, cut }).into() }
+    }
+
+    #[wasm_bindgen]
+    pub fn with_spending_plan(self, plan: SpendingPlan) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalSettings { spending_plan: Some(plan), ..self.withdrawal_settings } }
+    }
+
+    #[wasm_bindgen]
+    pub fn with_order(self, order: WithdrawalOrderJS) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalSettings { order: Some(order.order), ..self.withdrawal_settings } }
+    }
+
+    #[wasm_bindgen]
+    pub fn with_rmds(self, start_age: usize) -> WithdrawalSettingsJS {
+        assert!(start_age >= UNIFORM_LIFETIME_FIRST_AGE);
+
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalSettings { rmd_start_age: Some(start_age), ..self.withdrawal_settings } }
+    }
+
+    #[wasm_bindgen]
+    pub fn with_roth_conversions(self, settings: RothConversionSettings) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalSettings { roth_conversions: Some(settings), ..self.withdrawal_settings } }
+    }
+
+    #[wasm_bindgen]
+    pub fn with_social_security(self, settings: SocialSecuritySettings) -> WithdrawalSettingsJS {
+        WithdrawalSettingsJS { withdrawal_settings: WithdrawalSettings { social_security: Some(settings), ..self.withdrawal_settings } }
+    }
+}
+
+impl WithdrawalSettingsJS {
+    pub fn withdrawal_settings(&self) -> WithdrawalSettings {
+        self.withdrawal_settings.clone()
+    }
+}
+
+impl WithdrawalSettings {
+    pub fn create_strategy(&self, pre_retirement_monthly_income: f64) -> Box<dyn WithdrawalStrategy> {
+        let strategy = self.rule.create_strategy(pre_retirement_monthly_income);
+
+        match self.order {
+            Some(order) => Box::new(OrderedWithdrawal { strategy, order }),
+            None => strategy
+        }
+    }
+}
+
+impl WithdrawalRule {
+    pub fn create_strategy(&self, pre_retirement_monthly_income: f64) -> Box<dyn WithdrawalStrategy> {
+        match self {
+            WithdrawalRule::Orig => Box::new(WithdrawalStrategyOrig::new(pre_retirement_monthly_income)),
+            WithdrawalRule::SpendingPlan(cut) => Box::new(PlannedWithdrawal::new(*cut)),
+            WithdrawalRule::ConstantDollar { amount } => Box::new(ConstantDollarWithdrawal::new(*amount)),
+            WithdrawalRule::GuytonKlinger(settings) => Box::new(GuytonKlingerWithdrawal::new(*settings)),
+            WithdrawalRule::Vpw(settings) => Box::new(VpwWithdrawal::new(*settings)),
+            WithdrawalRule::VanguardDynamic(settings) => Box::new(VanguardDynamicWithdrawal::new(*settings)),
+            WithdrawalRule::Bucket(settings) => Box::new(BucketWithdrawal::new(*settings)),
+            WithdrawalRule::FloorAndUpside(settings) => Box::new(FloorAndUpsideWithdrawal::new(settings.clone()))
+        }
+    }
+}
+
+// Splits the withdrawal across accounts in proportion to their balances
+fn withdraw_pro_rata(withdrawal: f64, accounts: &mut [Account], period: Period) -> WithdrawalResult {
+    let total: f64 = accounts.iter().map(|a| a.balance()[period.get()]).sum();
+    if total <= 0.0 {
+        return WithdrawalResult { withdrawals: vec![0.0; accounts.len()], shortfall: withdrawal };
+    }
+
+    let withdrawals_per_account: Vec<f64> = accounts.iter().map(|a| (a.balance()[period.get()] / total) * withdrawal).collect();
+
+    let mut shortfall = 0.0;
+    let mut withdrawals = Vec::with_capacity(accounts.len());
+    for i in 0..accounts.len() {
+        let account_shortfall = accounts[i].attempt_withdrawal_with_shortfall(withdrawals_per_account[i], period);
+        shortfall += account_shortfall;
+        withdrawals.push(withdrawals_per_account[i] - account_shortfall);
+    }
+
+    WithdrawalResult { withdrawals, shortfall }
+}
+
+// Takes the withdrawal in the context's order if there is one, otherwise pro rata
+fn withdraw_from_accounts(withdrawal: f64, accounts: &mut [Account], context: &WithdrawalContext) -> WithdrawalResult {
+    match context.order {
+        Some(order) => withdraw_ordered(withdrawal, accounts, order, context),
+        None => withdraw_pro_rata(withdrawal, accounts, context.period)
+    }
+}
+
+// Withdraws from each account in turn until the withdrawal is covered
+fn withdraw_in_order(withdrawal: f64, accounts: &mut [Account], order: &[usize], period: Period) -> WithdrawalResult {
+    let mut remaining = withdrawal;
+    let mut withdrawals = vec![0.0; accounts.len()];
+    for &i in order {
+        let shortfall = accounts[i].attempt_withdrawal_with_shortfall(remaining, period);
+        withdrawals[i] = remaining - shortfall;
+        remaining = shortfall;
+    }
+
+    WithdrawalResult { withdrawals, shortfall: remaining }
+}
+
+// Tax-deferred withdrawals are ordinary income
+fn taxable_withdrawals(withdrawals: &[f64], accounts: &[Account]) -> f64 {
+    withdrawals.iter().zip(accounts)
+        .filter(|(_, account)| account.tax_treatment() == AccountTaxTreatment::TaxDeferred)
+        .map(|(withdrawal, _)| withdrawal)
+        .sum()
+}
+
+// Withdraws pro rata from the group of accounts, grossed up so that net of taxes it covers as much
+// of the net amount as it can without withdrawing more than max_gross.  Returns the net amount
+// covered.
+fn withdraw_net_pro_rata(net: f64, max_gross: f64, group: &[usize], accounts: &mut [Account], withdrawals: &mut [f64], context: &WithdrawalContext) -> f64 {
+    let period = context.period;
+    let balance: f64 = group.iter().map(|&i| accounts[i].balance()[period.get()]).sum();
+    let max_gross = f64::min(max_gross, balance);
+    if net <= 0.0 || max_gross <= 0.0 {
+        return 0.0;
+    }
+
+    let deferred_share = group.iter()
+        .filter(|&&i| accounts[i].tax_treatment() == AccountTaxTreatment::TaxDeferred)
+        .map(|&i| accounts[i].balance()[period.get()])
+        .sum::<f64>() / balance;
+    let already_taxable = taxable_withdrawals(withdrawals, accounts);
+    let taxes = |gross: f64| {
+        if deferred_share == 0.0 {
+            return 0.0;
+        }
+        context.tax.estimate_income_taxes(&Money::Taxable(already_taxable + gross * deferred_share), period)
+            - context.tax.estimate_income_taxes(&Money::Taxable(already_taxable), period)
+    };
+
+    // Converges as long as marginal rates are below 100%
+    let mut gross = f64::min(net, max_gross);
+    for _ in 0..100 {
+        let next = f64::min(net + taxes(gross), max_gross);
+        let converged = (next - gross).abs() < 1e-9;
+        gross = next;
+        if converged {
+            break;
+        }
+    }
+
+    for &i in group {
+        let withdrawal = accounts[i].balance()[period.get()] / balance * gross;
+        withdrawals[i] += withdrawal - accounts[i].attempt_withdrawal_with_shortfall(withdrawal, period);
+    }
+
+    if gross < max_gross {
+        net
+    } else {
+        gross - taxes(gross)
+    }
+}
+
+simplifying_assumption!("Bracket filling spreads the remaining room under the income ceiling evenly \
+    over the rest of the year, rather than predicting the year's other income.");
+// Takes the net withdrawal from the accounts in the given order, grossing up tax-deferred
+// withdrawals to cover their taxes.  The taxes themselves are collected by
+// collect_withdrawal_taxes.
+fn withdraw_ordered(withdrawal: f64, accounts: &mut [Account], order: WithdrawalOrder, context: &WithdrawalContext) -> WithdrawalResult {
+    let group = |accounts: &[Account], tax_treatment: AccountTaxTreatment| -> Vec<usize> {
+        (0..accounts.len()).filter(|&i| accounts[i].tax_treatment() == tax_treatment).collect()
+    };
+    let taxable = group(accounts, AccountTaxTreatment::Taxable);
+    let deferred = group(accounts, AccountTaxTreatment::TaxDeferred);
+    let tax_free = group(accounts, AccountTaxTreatment::TaxFree);
+
+    let steps = match order {
+        WithdrawalOrder::Proportional => vec![((0..accounts.len()).collect(), f64::INFINITY)],
+        WithdrawalOrder::TaxableFirst => vec![(taxable, f64::INFINITY), (deferred, f64::INFINITY), (tax_free, f64::INFINITY)],
+        WithdrawalOrder::BracketFilling { income_ceiling } => {
+            let months_left = 12 - (context.period.get() - context.period.round_down_to_year().get());
+            let room = (income_ceiling * context.cumulative_inflation() - context.tax.income_this_year(context.period)) / months_left as f64;
+
+            vec![(deferred.clone(), f64::max(room, 0.0)), (taxable, f64::INFINITY), (tax_free, f64::INFINITY), (deferred, f64::INFINITY)]
+        }
+    };
+
+    withdraw_net_in_steps(withdrawal, accounts, steps, context)
+}
+
+// Takes the net withdrawal from each group of accounts in turn, up to each group's maximum gross
+// withdrawal
+fn withdraw_net_in_steps(withdrawal: f64, accounts: &mut [Account], steps: Vec<(Vec<usize>, f64)>, context: &WithdrawalContext) -> WithdrawalResult {
+    let mut withdrawals = vec![0.0; accounts.len()];
+    let mut remaining = withdrawal;
+    for (group, max_gross) in steps {
+        remaining -= withdraw_net_pro_rata(remaining, max_gross, &group, accounts, &mut withdrawals, context);
+    }
+
+    WithdrawalResult { withdrawals, shortfall: remaining }
+}
+
+// Collects the income taxes on the tax-deferred part of a withdrawal
+pub fn collect_withdrawal_taxes(result: &WithdrawalResult, accounts: &[Account], tax: &mut dyn TaxCollector, period: Period) {
+    let taxable = taxable_withdrawals(&result.withdrawals, accounts);
+    if taxable > 0.0 {
+        tax.collect_income_taxes(Money::Taxable(taxable), period);
+    }
+}
+
+// IRS Uniform Lifetime Table distribution periods, from age 72 through 120 and over
+const UNIFORM_LIFETIME_FIRST_AGE: usize = 72;
+const UNIFORM_LIFETIME_TABLE: [f64; 49] = [
+    27.4, 26.5, 25.5, 24.6, 23.7, 22.9, 22.0, 21.1, 20.2, 19.4,
+    18.5, 17.7, 16.8, 16.0, 15.2, 14.4, 13.7, 12.9, 12.2, 11.5,
+    10.8, 10.1, 9.5, 8.9, 8.4, 7.8, 7.3, 6.8, 6.4, 6.0,
+    5.6, 5.2, 4.9, 4.6, 4.3, 4.1, 3.9, 3.7, 3.5, 3.4,
+    3.3, 3.1, 3.0, 2.9, 2.8, 2.7, 2.5, 2.3, 2.0
+];
+
+fn uniform_lifetime_divisor(age: usize) -> f64 {
+    let index = age.saturating_sub(UNIFORM_LIFETIME_FIRST_AGE);
+    UNIFORM_LIFETIME_TABLE[usize::min(index, UNIFORM_LIFETIME_TABLE.len() - 1)]
+}
+
+simplifying_assumption!("Required minimum distributions are calculated on the combined balance of \
+    all tax-deferred accounts, as with IRAs, and taken in equal monthly amounts over the year.");
+// Forces distributions from tax-deferred accounts once the start age is reached, on top of whatever
+// the withdrawal strategy already took from them.  Distributions beyond spending are taxed and
+// reinvested in a taxable account.
+pub struct RequiredMinimumDistributions {
+    start_age: usize,
+    year: Option<Period>,
+    // Still to be distributed this year
+    remaining: f64
+}
+
+impl RequiredMinimumDistributions {
+    pub fn new(start_age: usize) -> RequiredMinimumDistributions {
+        RequiredMinimumDistributions { start_age, year: None, remaining: 0.0 }
+    }
+
+    // The prior year-end balance divided by the distribution period for the age reached this year
+    fn required_for_year(&self, accounts: &[Account], year: Period, age_months: usize) -> f64 {
+        let age = age_months.div_ceil(12);
+        if age < self.start_age {
+            return 0.0;
+        }
 
+        let balance: f64 = accounts.iter()
+            .filter(|a| a.tax_treatment() == AccountTaxTreatment::TaxDeferred)
+            .map(|a| a.balance_before(year))
+            .sum();
+
+        balance / uniform_lifetime_divisor(age)
+    }
+
+    // Called after the period's withdrawal, returns the extra amount distributed
+    pub fn distribute(&mut self, result: &WithdrawalResult, accounts: &mut [Account], tax: &mut dyn TaxCollector, period: Period, age_months: usize) -> f64 {
+        let year = period.round_down_to_year();
+        if self.year.map(|y| y.get()) != Some(year.get()) {
+            let age_at_year_start = age_months - (period.get() - year.get());
+            self.remaining = self.required_for_year(accounts, year, age_at_year_start);
+            self.year = Some(year);
+        }
+
+        let months_left = 12 - (period.get() - year.get());
+        let required = self.remaining / months_left as f64;
+        let withdrawn = taxable_withdrawals(&result.withdrawals, accounts);
+        let extra = f64::max(required - withdrawn, 0.0);
+        self.remaining = f64::max(self.remaining - withdrawn, 0.0);
+        if extra == 0.0 {
+            return 0.0;
+        }
+
+        let deferred: Vec<usize> = (0..accounts.len()).filter(|&i| accounts[i].tax_treatment() == AccountTaxTreatment::TaxDeferred).collect();
+        let balance: f64 = deferred.iter().map(|&i| accounts[i].balance()[period.get()]).sum();
+        let extra = f64::min(extra, balance);
+        if extra <= 0.0 {
+            return 0.0;
+        }
+
+        for &i in &deferred {
+            let distribution = accounts[i].balance()[period.get()] / balance * extra;
+            accounts[i].withdraw_from_period(distribution, period);
+        }
+        self.remaining = f64::max(self.remaining - extra, 0.0);
+
+        let reinvest = accounts.iter().position(|a| a.tax_treatment() == AccountTaxTreatment::Taxable)
+            .expect("RMDs need a taxable account to reinvest in");
+        let leftover = tax.collect_income_taxes(Money::Taxable(extra), period).leftover();
+        accounts[reinvest].deposit(leftover, period);
+
+        extra
+    }
+}
+
+simplifying_assumption!("Each account keeps its own buckets, sized by its share of the portfolio, \
+    rather than holding the whole cash bucket in one account.");
+pub struct BucketWithdrawal {
+    settings: BucketSettings,
+    spending: ConstantDollarWithdrawal,
+    annual_withdrawal: f64
+}
+
+impl BucketWithdrawal {
+    pub fn new(settings: BucketSettings) -> BucketWithdrawal {
+        BucketWithdrawal {
+            settings,
+            spending: ConstantDollarWithdrawal::new(ConstantDollarAmount::InitialRate(settings.initial_rate)),
+            annual_withdrawal: 0.0
+        }
+    }
+
+    fn refill(&self, accounts: &mut [Account], context: &WithdrawalContext) {
+        let period = context.period;
+        let portfolio: f64 = accounts.iter().map(|a| a.balance()[period.get()]).sum();
+        let stocks_up = context.last_year_rates().iter().map(|r| r.stocks()).product::<f64>() > 1.0;
+        let sell_stocks = stocks_up || !self.settings.refill_only_after_gains;
+
+        for account in accounts.iter_mut() {
+            let share = if portfolio > 0.0 { account.balance()[period.get()] / portfolio } else { 0.0 };
+            let cash_target = share * self.settings.cash_years * self.annual_withdrawal;
+            let bonds_target = share * self.settings.bond_years * self.annual_withdrawal;
+            if let Some(buckets) = account.buckets_mut() {
+                if sell_stocks {
+                    let to_cash = f64::min(f64::max(cash_target - buckets.cash, 0.0), buckets.stocks);
+                    buckets.stocks -= to_cash;
+                    buckets.cash += to_cash;
+
+                    let to_bonds = f64::min(f64::max(bonds_target - buckets.bonds, 0.0), buckets.stocks);
+                    buckets.stocks -= to_bonds;
+                    buckets.bonds += to_bonds;
+                }
+
+                let to_cash = f64::min(f64::max(cash_target - buckets.cash, 0.0), buckets.bonds);
+                buckets.bonds -= to_cash;
+                buckets.cash += to_cash;
+            }
+        }
+    }
+}
+
+impl WithdrawalStrategy for BucketWithdrawal {
+    fn withdrawal_amount(&mut self, accounts: &[Account], context: &WithdrawalContext) -> f64 {
+        let monthly = self.spending.withdrawal_amount(accounts, context);
+        // Buckets only need to cover what guaranteed income doesn't
+        self.annual_withdrawal = f64::max(monthly - context.guaranteed_income, 0.0) * 12.0;
+
+        monthly
+    }
+
+    fn withdraw(&mut self, withdrawal: f64, accounts: &mut [Account], context: &WithdrawalContext) -> WithdrawalResult {
+        let period = context.period;
+        if context.is_retirement() {
+            let portfolio: f64 = accounts.iter().map(|a| a.balance()[period.get()]).sum();
+            for account in accounts.iter_mut() {
+                let share = if portfolio > 0.0 { account.balance()[period.get()] / portfolio } else { 0.0 };
+                account.split_into_buckets(share * self.settings.cash_years * self.annual_withdrawal, share * self.settings.bond_years * self.annual_withdrawal, period);
+            }
+        } else if context.is_anniversary() {
+            self.refill(accounts, context);
+        }
+
+        withdraw_from_accounts(withdrawal, accounts, context)
+    }
+}
+
+simplifying_assumption!("The bond ladder is bought out of every account pro rata, and buying it \
+    counts as a withdrawal, so buying it with tax-deferred money is taxed up front.");
+pub struct FloorAndUpsideWithdrawal {
+    settings: FloorAndUpsideSettings,
+    ladder: Option<BondLadder>
+}
+
+impl FloorAndUpsideWithdrawal {
+    pub fn new(settings: FloorAndUpsideSettings) -> FloorAndUpsideWithdrawal {
+        FloorAndUpsideWithdrawal { settings, ladder: None }
+    }
+
+    // Buys as much of a ladder matching planned essential spending as the accounts can afford
+    fn buy_ladder(&mut self, accounts: &mut [Account], context: &WithdrawalContext) -> WithdrawalResult {
+        let period = context.period;
+        let inflation = context.cumulative_inflation();
+        let payouts: Vec<f64> = (0..self.settings.ladder_years)
+            .map(|year| context.spending_plan.monthly_spending(context.age_months + year * 12, inflation).essential * 12.0)
+            .collect();
+
+        let cost = BondLadder::price(&payouts, &self.settings.yield_curve);
+        let portfolio: f64 = accounts.iter().map(|a| a.balance()[period.get()]).sum();
+        let affordable = if cost > portfolio { portfolio / cost } else { 1.0 };
+
+        let payouts = payouts.iter().map(|payout| payout * affordable).collect();
+        self.ladder = Some(BondLadder::new(period, payouts, self.settings.inflation_indexed));
+
+        withdraw_pro_rata(cost * affordable, accounts, period)
+    }
+}
+
+impl WithdrawalStrategy for FloorAndUpsideWithdrawal {
+    fn withdrawal_amount(&mut self, _accounts: &[Account], context: &WithdrawalContext) -> f64 {
+        context.planned_spending().total()
+    }
+
+    fn withdraw(&mut self, withdrawal: f64, accounts: &mut [Account], context: &WithdrawalContext) -> WithdrawalResult {
+        let purchase = if context.is_retirement() { Some(self.buy_ladder(accounts, context)) } else { None };
+        let payout = self.ladder.as_ref().map_or(0.0, |ladder| ladder.monthly_payout(context.period, context.rates));
+
+        let mut result = withdraw_from_accounts(f64::max(withdrawal - payout, 0.0), accounts, context);
+        if let Some(purchase) = purchase {
+            for (withdrawal, bought) in result.withdrawals.iter_mut().zip(purchase.withdrawals) {
+                *withdrawal += bought;
+            }
+        }
+
+        result
+    }
+}
+
+simplifying_assumption!("Taxes on Roth conversions are withheld from the conversion rather than \
+    paid from taxable accounts, which understates how much ends up in the Roth.");
+pub struct RothConversions {
+    settings: RothConversionSettings
+}
+
+impl RothConversions {
+    pub fn new(settings: RothConversionSettings) -> RothConversions {
+        RothConversions { settings }
+    }
+
+    // Called every period after withdrawals, only converts in the last period of each year so the
+    // year's other income is known.  Returns the amount converted.
+    pub fn convert(&self, accounts: &mut [Account], tax: &mut dyn TaxCollector, rates: &[Rate], period: Period, age_months: usize) -> f64 {
+        if !(period + 1).is_new_year() || age_months / 12 >= self.settings.end_age {
+            return 0.0;
+        }
+
+        let inflation = cumulative_inflation(rates, period);
+        let amount = match self.settings.amount {
+            RothConversionAmount::FillTo { income_ceiling } => income_ceiling * inflation - tax.income_this_year(period),
+            RothConversionAmount::Fixed { annual_amount } => annual_amount * inflation
+        };
+
+        let deferred: Vec<usize> = (0..accounts.len()).filter(|&i| accounts[i].tax_treatment() == AccountTaxTreatment::TaxDeferred).collect();
+        let balance: f64 = deferred.iter().map(|&i| accounts[i].balance()[period.get()]).sum();
+        let amount = f64::min(amount, balance);
+        if amount <= 0.0 {
+            return 0.0;
+        }
+
+        for &i in &deferred {
+            let conversion = accounts[i].balance()[period.get()] / balance * amount;
+            accounts[i].withdraw_from_period(conversion, period);
+        }
+
+        let roth = accounts.iter().position(|a| a.tax_treatment() == AccountTaxTreatment::TaxFree)
+            .expect("Roth conversions need a tax-free account to convert into");
+        let leftover = tax.collect_income_taxes(Money::Taxable(amount), period).leftover();
+        accounts[roth].deposit(leftover, period);
+
+        amount
+    }
+}
+
+// Wraps a rule so that its withdrawals are taken from accounts in a tax-aware order, while the rule
+// still decides everything else about them
+struct OrderedWithdrawal {
+    strategy: Box<dyn WithdrawalStrategy>,
+    order: WithdrawalOrder
+}
+
+impl WithdrawalStrategy for OrderedWithdrawal {
+    fn withdrawal_amount(&mut self, accounts: &[Account], context: &WithdrawalContext) -> f64 {
+        self.strategy.withdrawal_amount(accounts, context)
+    }
+
+    fn withdraw(&mut self, withdrawal: f64, accounts: &mut [Account], context: &WithdrawalContext) -> WithdrawalResult {
+        let context = WithdrawalContext { order: Some(self.order), ..*context };
+        self.strategy.withdraw(withdrawal, accounts, &context)
+    }
+}
+
+pub struct WithdrawalStrategyOrig {
+    monthly_withdrawal: f64
 }
 
 impl WithdrawalStrategyOrig {
-    pub fn new() -> WithdrawalStrategyOrig {
-        WithdrawalStrategyOrig { }
+    pub fn new(monthly_withdrawal: f64) -> WithdrawalStrategyOrig {
+        WithdrawalStrategyOrig { monthly_withdrawal }
     }
 }
 
 impl WithdrawalStrategy for WithdrawalStrategyOrig {
-    fn execute(&self, withdrawal: f64, accounts: &mut Vec<Account>, period: Period) -> Result<(), f64> {
-        let total: f64 = accounts.iter().map(|a| a.balance()[period.get()]).sum();
-        let withdrawals_per_account: Vec<f64> = accounts.iter().map(|a| (a.balance()[period.get()] / total) * withdrawal).collect();
-        
-        let mut shortfall = 0.0;
-        for i in 0..accounts.len() {
-            shortfall += accounts[i].attempt_withdrawal_with_shortfall(withdrawals_per_account[i], period);
+    fn withdrawal_amount(&mut self, _accounts: &[Account], _context: &WithdrawalContext) -> f64 {
+        self.monthly_withdrawal
+    }
+}
+
+pub struct PlannedWithdrawal {
+    cut: DiscretionaryCut
+}
+
+impl PlannedWithdrawal {
+    pub fn new(cut: DiscretionaryCut) -> PlannedWithdrawal {
+        PlannedWithdrawal { cut }
+    }
+}
+
+impl WithdrawalStrategy for PlannedWithdrawal {
+    fn withdrawal_amount(&mut self, _accounts: &[Account], context: &WithdrawalContext) -> f64 {
+        let spending = context.planned_spending();
+
+        if spending.total() * 12.0 > context.portfolio() * self.cut.max_withdrawal_rate {
+            spending.essential + spending.discretionary * (1.0 - self.cut.cut)
+        } else {
+            spending.total()
+        }
+    }
+}
+
+// The "4% rule": the same real amount every month, adjusted for inflation on each anniversary of
+// retirement
+simplifying_assumption!("Constant-dollar spending is withdrawn in equal monthly amounts that only \
+    change once a year, rather than as an annual lump sum.");
+pub struct ConstantDollarWithdrawal {
+    amount: ConstantDollarAmount,
+    annual_withdrawal: f64
+}
+
+impl ConstantDollarWithdrawal {
+    pub fn new(amount: ConstantDollarAmount) -> ConstantDollarWithdrawal {
+        ConstantDollarWithdrawal { amount, annual_withdrawal: 0.0 }
+    }
+}
+
+impl WithdrawalStrategy for ConstantDollarWithdrawal {
+    fn withdrawal_amount(&mut self, _accounts: &[Account], context: &WithdrawalContext) -> f64 {
+        if context.is_retirement() {
+            self.annual_withdrawal = match self.amount {
+                ConstantDollarAmount::InitialRate(rate) => context.portfolio() * rate,
+                ConstantDollarAmount::Target(spending) => spending * context.cumulative_inflation()
+            };
+        } else if context.is_anniversary() {
+            self.annual_withdrawal *= context.inflation_since(context.period - 12);
+        }
+
+        // An initial rate sets what comes out of the portfolio, with guaranteed income on top
+        match self.amount {
+            ConstantDollarAmount::InitialRate(_) => self.annual_withdrawal / 12.0 + context.guaranteed_income,
+            ConstantDollarAmount::Target(_) => self.annual_withdrawal / 12.0
+        }
+    }
+}
+
+simplifying_assumption!("Accounts are rebalanced every month, so Guyton-Klinger's portfolio \
+    management rule can only choose which accounts to withdraw from, not which asset classes.  \
+    After a year stocks lost money it takes priority over any withdrawal order, though \
+    withdrawals are still grossed up for taxes.");
+pub struct GuytonKlingerWithdrawal {
+    settings: GuytonKlingerSettings,
+    annual_withdrawal: f64,
+    // Whether stocks lost money over the last year, for the portfolio management rule
+    stocks_down: bool
+}
+
+impl GuytonKlingerWithdrawal {
+    pub fn new(settings: GuytonKlingerSettings) -> GuytonKlingerWithdrawal {
+        GuytonKlingerWithdrawal { settings, annual_withdrawal: 0.0, stocks_down: false }
+    }
+
+    fn apply_decision_rules(&mut self, context: &WithdrawalContext) {
+        let portfolio = context.portfolio();
+        self.stocks_down = context.last_year_rates().iter().map(|r| r.stocks()).product::<f64>() < 1.0;
+
+        // Once the portfolio has run out there's no withdrawal rate to apply the rules to, so
+        // spending just keeps up with inflation
+        let portfolio_at_year_start = context.portfolio_history[(context.period - 12).get()];
+        if portfolio <= 0.0 || portfolio_at_year_start <= 0.0 {
+            self.annual_withdrawal *= context.inflation_since(context.period - 12);
+            return;
+        }
+
+        // Skip the inflation adjustment after a year the portfolio lost money, if the withdrawal
+        // rate has crept above where it started
+        let portfolio_return = (portfolio + context.last_year_withdrawals()) / portfolio_at_year_start - 1.0;
+        let down_year = portfolio_return < 0.0 && self.annual_withdrawal / portfolio > self.settings.initial_rate;
+        if !down_year {
+            self.annual_withdrawal *= context.inflation_since(context.period - 12);
+        }
+
+        let current_rate = self.annual_withdrawal / portfolio;
+        if current_rate > self.settings.initial_rate * (1.0 + self.settings.capital_preservation_threshold) {
+            if context.years_retired() < self.settings.capital_preservation_years {
+                self.annual_withdrawal *= 1.0 - self.settings.capital_preservation_cut;
+            }
+        } else if current_rate < self.settings.initial_rate * (1.0 - self.settings.prosperity_threshold) {
+            self.annual_withdrawal *= 1.0 + self.settings.prosperity_raise;
+        }
+    }
+}
+
+impl WithdrawalStrategy for GuytonKlingerWithdrawal {
+    fn withdrawal_amount(&mut self, _accounts: &[Account], context: &WithdrawalContext) -> f64 {
+        if context.is_retirement() {
+            self.annual_withdrawal = context.portfolio() * self.settings.initial_rate;
+        } else if context.is_anniversary() {
+            self.apply_decision_rules(context);
+        }
+
+        self.annual_withdrawal / 12.0 + context.guaranteed_income
+    }
+
+    fn withdraw(&mut self, withdrawal: f64, accounts: &mut [Account], context: &WithdrawalContext) -> WithdrawalResult {
+        if self.settings.portfolio_management && self.stocks_down {
+            let mut order: Vec<usize> = (0..accounts.len()).collect();
+            order.sort_by(|a, b| accounts[*a].stocks(context.period).total_cmp(&accounts[*b].stocks(context.period)));
+
+            // Still grossed up for taxes when there's a withdrawal order
+            match context.order {
+                Some(_) => withdraw_net_in_steps(withdrawal, accounts, order.into_iter().map(|i| (vec![i], f64::INFINITY)).collect(), context),
+                None => withdraw_in_order(withdrawal, accounts, &order, context.period)
+            }
+        } else {
+            withdraw_from_accounts(withdrawal, accounts, context)
         }
+    }
+}
 
-        if shortfall != 0.0 {
-            Err(shortfall)
+// Annual payment, made at the start of each year, that spends down the portfolio over the given
+// number of years
+fn amortized_payment(portfolio: f64, rate: f64, years: f64) -> f64 {
+    if rate == 0.0 {
+        portfolio / years
+    } else {
+        portfolio * rate / ((1.0 - (1.0 + rate).powf(-years)) * (1.0 + rate))
+    }
+}
+
+simplifying_assumption!("VPW's expected returns don't depend on valuations or interest rates at \
+    the time of retirement.");
+pub struct VpwWithdrawal {
+    settings: VpwSettings,
+    annual_withdrawal: f64
+}
+
+impl VpwWithdrawal {
+    pub fn new(settings: VpwSettings) -> VpwWithdrawal {
+        VpwWithdrawal { settings, annual_withdrawal: 0.0 }
+    }
+
+    fn horizon_years(&self, context: &WithdrawalContext) -> f64 {
+        let years = match self.settings.horizon {
+            VpwHorizon::EndAge(end_age) => end_age as f64 - context.age_months as f64 / 12.0,
+            VpwHorizon::LifeExpectancy => context.remaining_life_expectancy
+        };
+
+        // Past the end of the horizon, spend whatever is left over the next year
+        f64::max(years, 1.0)
+    }
+
+    fn expected_real_return(&self, accounts: &[Account], context: &WithdrawalContext) -> f64 {
+        let period = context.period;
+        let portfolio = context.portfolio();
+        let stocks = if portfolio > 0.0 {
+            accounts.iter().map(|a| a.balance_before(period) * a.stocks(period)).sum::<f64>() / portfolio
         } else {
-            Ok(())
+            0.0
+        };
+
+        stocks * self.settings.stocks_real_return + (1.0 - stocks) * self.settings.bonds_real_return
+    }
+}
+
+impl WithdrawalStrategy for VpwWithdrawal {
+    fn withdrawal_amount(&mut self, accounts: &[Account], context: &WithdrawalContext) -> f64 {
+        if context.is_retirement() || context.is_anniversary() {
+            let rate = (1.0 + self.expected_real_return(accounts, context)) / (1.0 + self.settings.tilt) - 1.0;
+
+            self.annual_withdrawal = amortized_payment(context.portfolio(), rate, self.horizon_years(context));
         }
+
+        self.annual_withdrawal / 12.0 + context.guaranteed_income
+    }
+}
+
+pub struct VanguardDynamicWithdrawal {
+    settings: VanguardDynamicSettings,
+    annual_withdrawal: f64
+}
+
+impl VanguardDynamicWithdrawal {
+    pub fn new(settings: VanguardDynamicSettings) -> VanguardDynamicWithdrawal {
+        VanguardDynamicWithdrawal { settings, annual_withdrawal: 0.0 }
+    }
+}
+
+impl WithdrawalStrategy for VanguardDynamicWithdrawal {
+    fn withdrawal_amount(&mut self, _accounts: &[Account], context: &WithdrawalContext) -> f64 {
+        let target = context.portfolio() * self.settings.rate;
+
+        if context.is_retirement() {
+            self.annual_withdrawal = target;
+        } else if context.is_anniversary() {
+            let last_year = self.annual_withdrawal * context.inflation_since(context.period - 12);
+
+            self.annual_withdrawal = target.clamp(last_year * (1.0 + self.settings.floor), last_year * (1.0 + self.settings.ceiling));
+        }
+
+        self.annual_withdrawal / 12.0 + context.guaranteed_income
     }
 }
 
@@ -37,36 +1137,476 @@ impl WithdrawalStrategy for WithdrawalStrategyOrig {
 mod tests {
     use std::rc::Rc;
 
+    use assert_float_eq::*;
+
     use super::*;
-    use crate::assets::{AssetAllocation,AccountSettings};
+    use crate::assets::{AssetAllocation,AccountSettings,FeeSettings};
     use crate::montecarlo::Lifespan;
-    use crate::rates::Rate;
+    use crate::taxes::{MockTaxCollector,Tax,TaxBracket,TaxSettings};
+    use crate::util::tests::assert_vecfloat_absolute;
+
+    // Invests the accounts every period and runs the strategy from retirement on, the same way
+    // Run::populate does
+    fn run_strategy(strategy: Box<dyn WithdrawalStrategy>, accounts: &mut [Account], rates: &[Rate], retirement: usize) -> Vec<WithdrawalResult> {
+        run_strategy_with_tax(strategy, accounts, rates, retirement, &mut MockTaxCollector::default(), &SpendingPlan::default())
+    }
+
+    fn run_strategy_with_tax(mut strategy: Box<dyn WithdrawalStrategy>, accounts: &mut [Account], rates: &[Rate], retirement: usize, tax: &mut dyn TaxCollector, spending_plan: &SpendingPlan) -> Vec<WithdrawalResult> {
+        let mut portfolio_history = vec![];
+        let mut past_withdrawals = vec![];
+        let mut results = vec![];
+
+        for period in 0..rates.len() {
+            let period = Period::new(period);
+            portfolio_history.push(accounts.iter().map(|a| a.balance_before(period)).sum());
+            for account in accounts.iter_mut() {
+                account.rebalance_and_invest_next_period(period);
+            }
+
+            if period.get() >= retirement {
+                let context = WithdrawalContext {
+                    period,
+                    retirement: Period::new(retirement),
+                    age_months: 65 * 12 + period.get(),
+                    remaining_life_expectancy: 20.0,
+                    rates,
+                    portfolio_history: &portfolio_history,
+                    past_withdrawals: &past_withdrawals,
+                    tax,
+                    spending_plan,
+                    guaranteed_income: 0.0,
+                    order: None
+                };
+
+                let result = strategy.execute(accounts, &context);
+                collect_withdrawal_taxes(&result, accounts, tax, period);
+                past_withdrawals.push(result.total());
+                results.push(result);
+            }
+        }
+
+        results
+    }
+
+    fn create_account(starting_balance: f64, stocks: f64, rates: &Rc<Vec<Rate>>) -> Account {
+        AccountSettings::new(starting_balance, Rc::new(AssetAllocation::new(vec![stocks; rates.len()]))).create_account(Lifespan::new(rates.len()), Rc::clone(rates))
+    }
+
+    fn create_account_with_tax_treatment(starting_balance: f64, tax_treatment: AccountTaxTreatment, rates: &Rc<Vec<Rate>>) -> Account {
+        let allocation = Rc::new(AssetAllocation::new(vec![1.0; rates.len()]));
+        AccountSettings::new_with_tax_treatment(starting_balance, allocation, Rc::new(FeeSettings::none()), tax_treatment).create_account(Lifespan::new(rates.len()), Rc::clone(rates))
+    }
+
+    fn totals(results: &[WithdrawalResult]) -> Vec<f64> {
+        results.iter().map(|r| r.total()).collect()
+    }
 
     #[test]
     pub fn withdrawalstrategyorig_executesuccess() {
-        let dummy_allocation = Rc::new(AssetAllocation::new(vec![1.0]));
-        let mut account1 = AccountSettings::new(1536.0, Rc::clone(&dummy_allocation)).create_account(Lifespan::new(1), Rc::new(vec![Rate::new(1.0, 1.0, 1.0)]));
-        let mut account2 = AccountSettings::new(512.0, dummy_allocation).create_account(Lifespan::new(1), Rc::new(vec![Rate::new(1.0, 1.0, 1.0)]));
-        account1.rebalance_and_invest_next_period(Period::new(0));
-        account2.rebalance_and_invest_next_period(Period::new(0));
+        let rates = Rc::new(vec![Rate::new(1.0, 1.0, 1.0)]);
+        let mut accounts = vec![create_account(1536.0, 1.0, &rates), create_account(512.0, 1.0, &rates)];
 
-        let mut accounts = vec![account1, account2];
+        let results = run_strategy(WithdrawalRule::Orig.create_strategy(512.0), &mut accounts, &rates, 0);
 
-        let strategy = WithdrawalStrategyOrig::new();
-        strategy.execute(512.0, &mut accounts, Period::new(0)).expect("should have enough");
+        assert_eq!(results[0].withdrawals, vec![384.0, 128.0]);
+        assert!(!results[0].is_shortfall());
     }
 
     #[test]
     pub fn withdrawalstrategyorig_executefailure() {
-        let dummy_allocation = Rc::new(AssetAllocation::new(vec![1.0]));
-        let mut account1 = AccountSettings::new(1536.0, Rc::clone(&dummy_allocation)).create_account(Lifespan::new(1), Rc::new(vec![Rate::new(1.0, 1.0, 1.0)]));
-        let mut account2 = AccountSettings::new(512.0, dummy_allocation).create_account(Lifespan::new(1), Rc::new(vec![Rate::new(1.0, 1.0, 1.0)]));
-        account1.rebalance_and_invest_next_period(Period::new(0));
-        account2.rebalance_and_invest_next_period(Period::new(0));
+        let rates = Rc::new(vec![Rate::new(1.0, 1.0, 1.0)]);
+        let mut accounts = vec![create_account(1536.0, 1.0, &rates), create_account(512.0, 1.0, &rates)];
+
+        let results = run_strategy(WithdrawalRule::Orig.create_strategy(4096.0), &mut accounts, &rates, 0);
+
+        assert_eq!(results[0].withdrawals, vec![1536.0, 512.0]);
+        assert_eq!(results[0].shortfall, 2048.0);
+    }
+
+    #[test]
+    pub fn withdrawinorder() {
+        let rates = Rc::new(vec![Rate::new(1.0, 1.0, 1.0)]);
+        let mut accounts = vec![create_account(1536.0, 1.0, &rates), create_account(512.0, 1.0, &rates)];
+        for account in accounts.iter_mut() {
+            account.rebalance_and_invest_next_period(Period::new(0));
+        }
+
+        let result = withdraw_in_order(1024.0, &mut accounts, &[1, 0], Period::new(0));
+        assert_eq!(result.withdrawals, vec![512.0, 512.0]);
+        assert_eq!(result.total(), 1024.0);
+        assert!(!result.is_shortfall());
+    }
 
-        let mut accounts = vec![account1, account2];
+    #[test]
+    pub fn plannedwithdrawal_withdrawalamount() {
+        // 50% inflation in the first month of retirement
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 13];
+        rates[0] = Rate::new(1.0, 1.0, 1.5);
+        let rates = Rc::new(rates);
+        let plan = SpendingPlan::new_from_js(12000.0, vec![], vec![], 12000.0, vec![66], vec![0.5]);
+
+        let mut accounts = vec![create_account(1000000.0, 1.0, &rates)];
+        let withdrawals = totals(&run_strategy_with_tax(WithdrawalRule::SpendingPlan(DiscretionaryCut::none()).create_strategy(0.0), &mut accounts, &rates, 0, &mut MockTaxCollector::default(), &plan));
+
+        assert_eq!(withdrawals[0], 2000.0);
+        assert_eq!(withdrawals[1], 3000.0);
+        // Discretionary spending halves at 66
+        assert_eq!(withdrawals[12], 2250.0);
+
+        // 36000 a year is more than 5% of 600000, so discretionary spending is cut by 75%
+        let mut accounts = vec![create_account(600000.0, 1.0, &rates)];
+        let cut = DiscretionaryCut { max_withdrawal_rate: 0.05, cut: 0.75 };
+        let withdrawals = totals(&run_strategy_with_tax(WithdrawalRule::SpendingPlan(cut).create_strategy(0.0), &mut accounts, &rates, 0, &mut MockTaxCollector::default(), &plan));
+        assert_eq!(withdrawals[0], 2000.0);
+        assert_eq!(withdrawals[1], 1500.0 + 375.0);
+    }
+
+    #[test]
+    pub fn floorandupside_ladder() {
+        // 50% inflation in the second month
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 80];
+        rates[1] = Rate::new(1.0, 1.0, 1.5);
+        let rates = Rc::new(rates);
+        let plan = SpendingPlan::new_from_js(12000.0, vec![], vec![], 12000.0, vec![], vec![]);
+        let run_ladder = |inflation_indexed: bool| {
+            let settings = FloorAndUpsideSettings::new(5, vec![0.0], inflation_indexed);
+            let mut accounts = vec![create_account(200000.0, 1.0, &rates)];
+            totals(&run_strategy_with_tax(WithdrawalRule::FloorAndUpside(settings).create_strategy(0.0), &mut accounts, &rates, 0, &mut MockTaxCollector::default(), &plan))
+        };
+
+        // Five years of essential spending at a 0% yield costs 60000, and the accounts only pay for
+        // discretionary spending
+        let withdrawals = run_ladder(true);
+        assert_eq!(withdrawals[0], 60000.0 + 1000.0);
+        assert_eq!(withdrawals[2], 1500.0);
+        // The ladder has run out
+        assert_eq!(withdrawals[60], 3000.0);
 
-        let strategy = WithdrawalStrategyOrig::new();
-        assert_eq!(2048.0, strategy.execute(4096.0, &mut accounts, Period::new(0)).expect_err("shouldn't have enough"));
+        // A nominal ladder doesn't keep up with inflation
+        let withdrawals = run_ladder(false);
+        assert_eq!(withdrawals[2], 2000.0);
     }
-}
\ No newline at end of file
+
+    #[test]
+    pub fn floorandupside_withorder() {
+        let rates = Rc::new(vec![Rate::new(1.0, 1.0, 1.0); 13]);
+        let plan = SpendingPlan::new_from_js(12000.0, vec![], vec![], 12000.0, vec![], vec![]);
+        let mut accounts = vec![
+            create_account_with_tax_treatment(100000.0, AccountTaxTreatment::Taxable, &rates),
+            create_account_with_tax_treatment(100000.0, AccountTaxTreatment::TaxDeferred, &rates)
+        ];
+        let settings = WithdrawalSettings { order: Some(WithdrawalOrder::TaxableFirst), ..WithdrawalRule::FloorAndUpside(FloorAndUpsideSettings::new(5, vec![0.0], true)).into() };
+        let mut tax = Tax::new(TaxSettings::new(vec![TaxBracket { floor: 0.0, rate: 0.1 }], false, 0.0, false), Rc::clone(&rates), Lifespan::new(13));
+        let results = run_strategy_with_tax(settings.create_strategy(0.0), &mut accounts, &rates, 0, &mut tax, &plan);
+
+        // The ladder is still bought pro rata, and its payout still covers essential spending, so
+        // only discretionary spending comes out of the taxable account
+        assert_eq!(results[0].withdrawals, vec![30000.0 + 1000.0, 30000.0]);
+        assert_eq!(results[12].withdrawals, vec![1000.0, 0.0]);
+    }
+
+    #[test]
+    pub fn constantdollar_withdrawalamount() {
+        // 12.5% inflation in the second month and 6.25% in the 14th
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 30];
+        rates[1] = Rate::new(1.0, 1.0, 1.125);
+        rates[13] = Rate::new(1.0, 1.0, 1.0625);
+        let rates = Rc::new(rates);
+
+        let mut accounts = vec![create_account(76800.0, 1.0, &rates)];
+        let withdrawals = totals(&run_strategy(WithdrawalRule::ConstantDollar { amount: ConstantDollarAmount::InitialRate(0.04) }.create_strategy(0.0), &mut accounts, &rates, 1));
+        assert_eq!(withdrawals[0], 256.0);
+        assert_eq!(withdrawals[11], 256.0);
+        // Inflation only catches up on the anniversary of retirement
+        assert_eq!(withdrawals[12], 288.0);
+        assert_eq!(withdrawals[24], 306.0);
+
+        // The target is in today's dollars, so it includes inflation before retirement too
+        let mut accounts = vec![create_account(76800.0, 1.0, &rates)];
+        let withdrawals = totals(&run_strategy(WithdrawalRule::ConstantDollar { amount: ConstantDollarAmount::Target(3072.0) }.create_strategy(0.0), &mut accounts, &rates, 2));
+        assert_eq!(withdrawals[0], 288.0);
+        assert_eq!(withdrawals[12], 306.0);
+    }
+
+    #[test]
+    pub fn guytonklinger_capitalpreservation_portfoliomanagement() {
+        // Stocks lose half their value in the first month, with 10% inflation later in the year
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 14];
+        rates[0] = Rate::new(0.5, 1.0, 1.0);
+        rates[5] = Rate::new(1.0, 1.0, 1.1);
+        let rates = Rc::new(rates);
+
+        let mut accounts = vec![create_account(60000.0, 1.0, &rates), create_account(60000.0, 0.0, &rates)];
+        let results = run_strategy(WithdrawalRule::GuytonKlinger(GuytonKlingerSettings::published(0.05)).create_strategy(0.0), &mut accounts, &rates, 0);
+
+        // The portfolio is down to 84000 after the first year, for a 7.1% withdrawal rate.  That
+        // skips the inflation adjustment and cuts spending by 10%.
+        assert_vecfloat_absolute(totals(&results), [vec![500.0; 12], vec![450.0; 2]].concat());
+        // After stocks lost money, withdrawals come from the bond account first
+        assert_eq!(results[12].withdrawals, vec![0.0, 450.0]);
+
+        // Even with a withdrawal order
+        let mut accounts = vec![create_account(60000.0, 1.0, &rates), create_account(60000.0, 0.0, &rates)];
+        let settings = WithdrawalSettings { order: Some(WithdrawalOrder::Proportional), ..WithdrawalRule::GuytonKlinger(GuytonKlingerSettings::published(0.05)).into() };
+        let results = run_strategy(settings.create_strategy(0.0), &mut accounts, &rates, 0);
+        assert_eq!(results[12].withdrawals, vec![0.0, 450.0]);
+
+        // Tax-deferred withdrawals taken bonds first are still grossed up to cover 10% taxes, so
+        // net spending is the same
+        let deferred = |stocks: f64| AccountSettings::new_with_tax_treatment(60000.0, Rc::new(AssetAllocation::new(vec![stocks; 14])), Rc::new(FeeSettings::none()), AccountTaxTreatment::TaxDeferred).create_account(Lifespan::new(14), Rc::clone(&rates));
+        let mut accounts = vec![deferred(1.0), deferred(0.0)];
+        let settings = WithdrawalSettings { order: Some(WithdrawalOrder::TaxableFirst), ..WithdrawalRule::GuytonKlinger(GuytonKlingerSettings::published(0.05)).into() };
+        let mut tax = Tax::new(TaxSettings::new(vec![TaxBracket { floor: 0.0, rate: 0.1 }], false, 0.0, false), Rc::clone(&rates), Lifespan::new(14));
+        let results = run_strategy_with_tax(settings.create_strategy(0.0), &mut accounts, &rates, 0, &mut tax, &SpendingPlan::default());
+        assert_float_absolute_eq!(results[12].withdrawals[0], 0.0);
+        assert_float_absolute_eq!(results[12].withdrawals[1] * 0.9, 450.0);
+        assert!(!results[12].is_shortfall());
+
+        let mut accounts = vec![create_account(120000.0, 0.5, &rates)];
+        let settings = GuytonKlingerSettings::new(0.05, 0.2, 0.1, 1, 0.2, 0.1, false);
+        let withdrawals = totals(&run_strategy(WithdrawalRule::GuytonKlinger(settings).create_strategy(0.0), &mut accounts, &rates, 0));
+
+        // The capital preservation rule no longer applies after the first year
+        assert_eq!(withdrawals[12..], [500.0; 2]);
+    }
+
+    #[test]
+    pub fn guytonklinger_depleted() {
+        // 10% inflation in the first year
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 25];
+        rates[5] = Rate::new(1.0, 1.0, 1.1);
+        let rates = Rc::new(rates);
+
+        // Withdrawing the whole portfolio in the first year leaves nothing to base a rate on
+        let mut accounts = vec![create_account(6000.0, 0.5, &rates)];
+        let results = run_strategy(WithdrawalRule::GuytonKlinger(GuytonKlingerSettings::published(1.0)).create_strategy(0.0), &mut accounts, &rates, 0);
+
+        // Spending keeps up with inflation, all of it shortfall
+        assert_float_absolute_eq!(results[12].shortfall, 6600.0 / 12.0);
+        assert_float_absolute_eq!(results[24].shortfall, 6600.0 / 12.0);
+    }
+
+    #[test]
+    pub fn guytonklinger_prosperity() {
+        // Stocks double in the first month, with 10% inflation later in the year
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 13];
+        rates[0] = Rate::new(2.0, 1.0, 1.0);
+        rates[5] = Rate::new(1.0, 1.0, 1.1);
+        let rates = Rc::new(rates);
+
+        let mut accounts = vec![create_account(120000.0, 1.0, &rates)];
+        let withdrawals = totals(&run_strategy(WithdrawalRule::GuytonKlinger(GuytonKlingerSettings::published(0.05)).create_strategy(0.0), &mut accounts, &rates, 0));
+
+        // 6600 after inflation is a 2.8% withdrawal rate on 234000, which earns a 10% raise
+        assert_float_absolute_eq!(withdrawals[12], 6600.0 * 1.1 / 12.0);
+    }
+
+    #[test]
+    pub fn amortizedpayment() {
+        assert_eq!(amortized_payment(1000.0, 0.0, 4.0), 250.0);
+        assert_float_absolute_eq!(amortized_payment(1000.0, 0.05, 1.0), 1000.0);
+        assert_float_absolute_eq!(amortized_payment(100000.0, 0.05, 30.0), 6195.3748, 0.0001);
+    }
+
+    #[test]
+    pub fn vpw_withdrawalamount() {
+        let rates = Rc::new(vec![Rate::new(1.0, 1.0, 1.0); 13]);
+        let run_vpw = |settings: VpwSettings| {
+            let mut accounts = vec![create_account(90000.0, 1.0, &rates), create_account(30000.0, 0.0, &rates)];
+            totals(&run_strategy(WithdrawalRule::Vpw(settings).create_strategy(0.0), &mut accounts, &rates, 0))
+        };
+
+        // 75% stocks at 6% and 25% bonds at 2% is a 5% expected real return, over 30 years to age 95
+        let withdrawals = run_vpw(VpwSettings::new(0.06, 0.02, VpwHorizon::EndAge(95), 0.0));
+        assert_float_absolute_eq!(withdrawals[0], 6195.3748 * 1.2 / 12.0, 0.0001);
+        // Only recalculated once a year
+        assert_eq!(withdrawals[0], withdrawals[11]);
+        assert!(withdrawals[12] != withdrawals[11]);
+
+        // Tilting by the whole expected return spends the portfolio evenly
+        let withdrawals = run_vpw(VpwSettings::new(0.06, 0.02, VpwHorizon::EndAge(95), 0.05));
+        assert_float_absolute_eq!(withdrawals[0], 4000.0 / 12.0);
+
+        let withdrawals = run_vpw(VpwSettings::new(0.05, 0.05, VpwHorizon::LifeExpectancy, 0.0));
+        assert_float_absolute_eq!(withdrawals[0], amortized_payment(120000.0, 0.05, 20.0) / 12.0);
+    }
+
+    #[test]
+    pub fn vanguarddynamic_ceilingfloor() {
+        // 25% inflation in the first year, then stocks double, crash and recover
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 37];
+        rates[3] = Rate::new(1.0, 1.0, 1.25);
+        rates[5] = Rate::new(2.0, 1.0, 1.0);
+        rates[17] = Rate::new(0.25, 1.0, 1.0);
+        rates[29] = Rate::new(3.25, 1.0, 1.0);
+        let rates = Rc::new(rates);
+
+        let mut accounts = vec![create_account(96000.0, 1.0, &rates)];
+        let withdrawals = totals(&run_strategy(WithdrawalRule::VanguardDynamic(VanguardDynamicSettings::new(0.05, 0.05, -0.025)).create_strategy(0.0), &mut accounts, &rates, 0));
+
+        assert_eq!(withdrawals[0], 400.0);
+        // Portfolio doubled, but real spending can only go up 5% from 6000 after inflation
+        assert_float_absolute_eq!(withdrawals[12], 6000.0 * 1.05 / 12.0);
+        // Portfolio crashed, but real spending can only go down 2.5%
+        assert_float_absolute_eq!(withdrawals[24], 6300.0 * 0.975 / 12.0);
+        // In between, it's just 5% of the portfolio
+        let portfolio = (41968.75 - 5.0 * withdrawals[24]) * 3.25 - 7.0 * withdrawals[24];
+        assert_float_absolute_eq!(withdrawals[36], portfolio * 0.05 / 12.0);
+    }
+
+    #[test]
+    pub fn rmd_distribute() {
+        let rates = Rc::new(vec![Rate::new(1.0, 1.0, 1.0); 24]);
+        let mut accounts = vec![
+            create_account_with_tax_treatment(265000.0, AccountTaxTreatment::TaxDeferred, &rates),
+            create_account_with_tax_treatment(0.0, AccountTaxTreatment::Taxable, &rates)
+        ];
+        let settings = TaxSettings::new(vec![TaxBracket { floor: 0.0, rate: 0.1 }], false, 0.0, false);
+        let mut tax = Tax::new(settings, Rc::clone(&rates), Lifespan::new(24));
+        let mut rmds = RequiredMinimumDistributions::new(73);
+        let nothing = WithdrawalResult { withdrawals: vec![0.0; 2], shortfall: 0.0 };
+        for period in 0..14 {
+            for account in accounts.iter_mut() {
+                account.rebalance_and_invest_next_period(Period::new(period));
+            }
+        }
+
+        // Turning 72 this year, so nothing is required yet
+        assert_eq!(rmds.distribute(&nothing, &mut accounts, &mut tax, Period::new(0), 72 * 12 - 6), 0.0);
+
+        // 265000 / 26.5 at 73, a twelfth at a time, reinvested after the 10% tax
+        assert_float_absolute_eq!(rmds.distribute(&nothing, &mut accounts, &mut tax, Period::new(12), 73 * 12 - 6), 10000.0 / 12.0);
+        assert_float_absolute_eq!(accounts[1].balance()[12], 750.0);
+        assert_float_absolute_eq!(tax.income_this_year(Period::new(12)), 10000.0 / 12.0);
+
+        // Withdrawals for spending count towards the requirement
+        let spending = WithdrawalResult { withdrawals: vec![1000.0, 0.0], shortfall: 0.0 };
+        assert_eq!(rmds.distribute(&spending, &mut accounts, &mut tax, Period::new(13), 73 * 12 - 5), 0.0);
+    }
+
+    #[test]
+    pub fn rothconversions_convert() {
+        // 50% inflation before the conversion
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 24];
+        rates[3] = Rate::new(1.0, 1.0, 1.5);
+        let rates = Rc::new(rates);
+        let mut accounts = vec![
+            create_account_with_tax_treatment(100000.0, AccountTaxTreatment::TaxDeferred, &rates),
+            create_account_with_tax_treatment(0.0, AccountTaxTreatment::TaxFree, &rates)
+        ];
+        for period in 0..24 {
+            for account in accounts.iter_mut() {
+                account.rebalance_and_invest_next_period(Period::new(period));
+            }
+        }
+        let settings = TaxSettings::new(vec![TaxBracket { floor: 0.0, rate: 0.1 }], false, 0.0, false);
+        let mut tax = Tax::new(settings, Rc::clone(&rates), Lifespan::new(24));
+        tax.collect_income_taxes(Money::Taxable(5000.0), Period::new(5));
+        let conversions = RothConversions::new(RothConversionSettings::fill_to(20000.0, 66));
+
+        // Only at the end of the year
+        assert_eq!(conversions.convert(&mut accounts, &mut tax, &rates, Period::new(10), 65 * 12 + 10), 0.0);
+
+        // Up to 30000 after inflation, less the 5000 already earned, with the taxes withheld
+        assert_float_absolute_eq!(conversions.convert(&mut accounts, &mut tax, &rates, Period::new(11), 65 * 12 + 11), 25000.0);
+        assert_float_absolute_eq!(accounts[0].balance()[11], 75000.0);
+        assert_float_absolute_eq!(accounts[1].balance()[11], 22500.0);
+        assert_float_absolute_eq!(tax.total_taxes(), 3000.0);
+
+        // Past the end age
+        assert_eq!(conversions.convert(&mut accounts, &mut tax, &rates, Period::new(23), 66 * 12 + 11), 0.0);
+    }
+
+    #[test]
+    pub fn bucket_refill() {
+        // Stocks halve in the first year and double in the second
+        let mut rates = vec![Rate::new(1.0, 1.0, 1.0); 25];
+        rates[1] = Rate::new(0.5, 1.0, 1.0);
+        rates[13] = Rate::new(2.0, 1.0, 1.0);
+        let rates = Rc::new(rates);
+
+        let mut accounts = [create_account(100000.0, 1.0, &rates)];
+        let withdrawals = totals(&run_strategy(WithdrawalRule::Bucket(BucketSettings::new(0.04, 2.0, 3.0, true)).create_strategy(0.0), &mut accounts, &rates[..13], 0));
+        assert_float_absolute_eq!(withdrawals[12], 4000.0 / 12.0);
+
+        // After the down year, cash is refilled from bonds and stocks are left alone
+        let buckets = accounts[0].buckets().unwrap();
+        assert_float_absolute_eq!(buckets.cash, 8000.0 - 4000.0 / 12.0);
+        assert_float_absolute_eq!(buckets.bonds, 8000.0);
+        assert_float_absolute_eq!(buckets.stocks, 40000.0);
+
+        // After the up year, stocks refill both
+        let mut accounts = [create_account(100000.0, 1.0, &rates)];
+        run_strategy(WithdrawalRule::Bucket(BucketSettings::new(0.04, 2.0, 3.0, true)).create_strategy(0.0), &mut accounts, &rates, 0);
+        let buckets = accounts[0].buckets().unwrap();
+        assert_float_absolute_eq!(buckets.cash, 8000.0 - 4000.0 / 12.0);
+        assert_float_absolute_eq!(buckets.bonds, 12000.0);
+        assert_float_absolute_eq!(buckets.stocks, 80000.0 - 8000.0);
+    }
+
+    #[test]
+    pub fn bucket_withorder() {
+        let rates = Rc::new(vec![Rate::new(1.0, 1.0, 1.0); 13]);
+        let mut accounts = vec![
+            create_account_with_tax_treatment(50000.0, AccountTaxTreatment::Taxable, &rates),
+            create_account_with_tax_treatment(50000.0, AccountTaxTreatment::TaxFree, &rates)
+        ];
+        let settings = WithdrawalSettings { order: Some(WithdrawalOrder::TaxableFirst), ..WithdrawalRule::Bucket(BucketSettings::new(0.04, 2.0, 3.0, false)).into() };
+        let results = run_strategy(settings.create_strategy(0.0), &mut accounts, &rates, 0);
+
+        // Both accounts are split into buckets at retirement, but spending only comes out of the
+        // taxable account's cash
+        assert_float_absolute_eq!(results[0].withdrawals[0], 4000.0 / 12.0);
+        assert_eq!(results[0].withdrawals[1], 0.0);
+
+        // After a year the taxable account's cash has run out, and each account is refilled to its
+        // share of two years of spending
+        let taxable_share = 46000.0 / 96000.0;
+        let buckets = accounts[0].buckets().unwrap();
+        assert_float_absolute_eq!(buckets.cash, taxable_share * 8000.0 - 4000.0 / 12.0);
+        assert_float_absolute_eq!(buckets.bonds, 6000.0);
+        assert_float_absolute_eq!(accounts[1].buckets().unwrap().cash, (1.0 - taxable_share) * 8000.0);
+        assert_float_absolute_eq!(accounts[1].buckets().unwrap().bonds, (1.0 - taxable_share) * 12000.0);
+    }
+
+    #[test]
+    pub fn uniformlifetimedivisor() {
+        assert_eq!(uniform_lifetime_divisor(72), 27.4);
+        assert_eq!(uniform_lifetime_divisor(75), 24.6);
+        assert_eq!(uniform_lifetime_divisor(120), 2.0);
+        assert_eq!(uniform_lifetime_divisor(130), 2.0);
+    }
+
+    #[test]
+    pub fn withdrawordered_grossupfortaxes() {
+        let rates = Rc::new(vec![Rate::new(1.0, 1.0, 1.0); 1]);
+        let run_ordered = |withdrawal: f64, order: WithdrawalOrder| {
+            let mut accounts = vec![
+                create_account_with_tax_treatment(1000.0, AccountTaxTreatment::Taxable, &rates),
+                create_account_with_tax_treatment(10000.0, AccountTaxTreatment::TaxDeferred, &rates),
+                create_account_with_tax_treatment(10000.0, AccountTaxTreatment::TaxFree, &rates)
+            ];
+            let settings = TaxSettings::new(vec![TaxBracket { floor: 0.0, rate: 0.1 }], false, 0.0, false);
+            let mut tax = Tax::new(settings, Rc::clone(&rates), Lifespan::new(1));
+            let strategy = WithdrawalSettings { order: Some(order), ..WithdrawalRule::Orig.into() }.create_strategy(withdrawal);
+
+            let result = run_strategy_with_tax(strategy, &mut accounts, &rates, 0, &mut tax, &SpendingPlan::default()).remove(0);
+            (result, tax.income_this_year(Period::new(0)))
+        };
+
+        // 450 net from the traditional account takes 500 before the 10% tax
+        let (result, income) = run_ordered(1450.0, WithdrawalOrder::TaxableFirst);
+        assert!(!result.is_shortfall());
+        assert_vecfloat_absolute(result.withdrawals, vec![1000.0, 500.0, 0.0]);
+        assert_float_absolute_eq!(income, 500.0);
+
+        // Only 300 a year fits under the ceiling, spread over 12 months, and the rest comes from
+        // taxable then Roth
+        let (result, _) = run_ordered(1450.0, WithdrawalOrder::BracketFilling { income_ceiling: 3600.0 });
+        assert_vecfloat_absolute(result.withdrawals, vec![1000.0, 300.0, 180.0]);
+
+        let (result, _) = run_ordered(2100.0, WithdrawalOrder::Proportional);
+        // 2205 gross covers the tax on the traditional account's share
+        assert_vecfloat_absolute(result.withdrawals, vec![105.0, 1050.0, 1050.0]);
+    }
+}
@@ -1,4 +1,4 @@
-import {AccountContributionSettings, AccountContributionSettingsVec, AccountContributionSource, AccountContributionTaxability, AccountSettings, AssetAllocation, FicaJS, JobSettings, PersonSettings, RaiseSettings, RatesSourceHolder, Simulation, TaxSettings} from "../pkg/retirement.js";
+import {AccountContributionSettings, AccountContributionSettingsVec, AccountContributionSource, AccountContributionTaxability, AccountSettings, AssetAllocation, FicaJS, JobSettings, PersonSettings, RaiseSettings, RatesSourceHolder, Simulation, SimulationSettings, TaxSettings, WithdrawalSettingsJS} from "../pkg/retirement.js";
 import fs from "fs";
 import assert from "assert";
 
@@ -51,7 +51,10 @@ allAccountContributionSettings.add(accountContributionSettings);
 let jobSettings = new JobSettings(129000 / 12, FicaJS.exempt(), raiseSettings, allAccountContributionSettings);
 let personSettings = PersonSettings.new_with_custom_death_rates(27, 0, deathRates);
 let taxSettings = new TaxSettings([0, 10275, 41775, 89075, 170050, 215950, 539900], [0.1, 0.12, 0.22, 0.24, 0.32, 0.35, 0.37], true, 12950, true);
-let simulation = new Simulation(BigInt(1337), 100, RatesSourceHolder.new_from_custom_split(stocks, bonds, inflation), 12, jobSettings, personSettings, (65 - 27) * 12, taxSettings);
+let simulationSettings = new SimulationSettings(BigInt(1337), 100, RatesSourceHolder.new_from_custom_split(stocks, bonds, inflation), 12, personSettings, (65 - 27) * 12, taxSettings)
+    .with_job(jobSettings)
+    .with_withdrawal(WithdrawalSettingsJS.orig());
+let simulation = new Simulation(simulationSettings);
 
 assert.equal(simulation.success_rate().num, 48);
 assert.equal(simulation.success_rate().denom, 100);